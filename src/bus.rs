@@ -1,12 +1,48 @@
-use crate::error::{ReadError, TransferError, WriteError};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::clock::{Clock, DeviceClock, SystemClock, TimestampCheck};
+use crate::error::{
+    EncodeError, FrameValidationError, InvalidFrameLength, ParseError, ReadError, TransferError,
+    WriteError,
+};
 use crate::frames::{CanFdFrame, FdCanUSBFrame};
+use crate::stats::Stats;
+
+mod batch;
+mod channel;
+mod split;
+pub use batch::BatchWriter;
+pub use channel::{FrameSender, PendingWrite};
+pub use split::{FdCanUSBReader, FdCanUSBWriter};
+
+/// The time to wait for a packet from the [FdCanUSB], if no timeout is given.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+/// The number of recently read bytes kept, see [`FdCanUSB::recent_bytes`].
+const DEFAULT_RECENT_BYTES_LEN: usize = 512;
+/// How long the input must be quiet for before a retry, see [`FdCanUSB::transfer_with_retries`].
+const DRAIN_QUIET_TIME: Duration = Duration::from_millis(10);
+
+/// The configuration keys of the CAN bitrates, see [`FdCanUSB::set_bitrate`].
+const ARBITRATION_BITRATE_KEY: &str = "can.bitrate";
+const DATA_BITRATE_KEY: &str = "can.fd_bitrate";
+/// The fastest arbitration bitrate of a CAN bus.
+const MAX_ARBITRATION_BITRATE: u32 = 1_000_000;
+/// The fastest data bitrate of a CAN FD transceiver.
+const MAX_DATA_BITRATE: u32 = 8_000_000;
+
+/// The start of the banner the FdCanUSB sends when it resets, see [`FdCanUSB::wait_for_ready`].
+const BANNER_PREFIX: &[u8] = b"fdcanusb";
+
+/// The maximum data length of a written frame, if no other is set.
+const DEFAULT_MAX_DATA_LEN: usize = 64;
 
 /// FdCanUSB communications struct
 ///
 /// Can be used with any transport type that implements [`std::io::Write`] and [`std::io::Read`]
 /// The baud rate is unused, as the [FdCanUSB] communicates via USB CDC
 /// ### Example
-/// ```
+/// ```no_run
 /// use fdcanusb::{FdCanUSB, serial2};
 /// # fn main() -> Result<(), std::io::Error> {
 /// let transport = serial2::SerialPort::open("/dev/fdcanusb", serial2::KeepSettings)?;
@@ -17,7 +53,7 @@ use crate::frames::{CanFdFrame, FdCanUSBFrame};
 /// ## `serial2` Integration
 /// To use the `FdCanUSB` with a [`serial2::SerialPort`](https://docs.rs/serial2/latest/serial2/), you can use the [`FdCanUSB::open`] method.
 /// ### Example
-/// ```
+/// ```no_run
 /// use fdcanusb::FdCanUSB;
 ///
 /// let mut fdcanusb = FdCanUSB::open("/dev/fdcanusb", serial2::KeepSettings).expect("Failed to open serial port");
@@ -25,8 +61,11 @@ use crate::frames::{CanFdFrame, FdCanUSBFrame};
 
 #[derive(Debug)]
 
-pub struct FdCanUSB<T = serial2::SerialPort, Buffer = Vec<u8>>
-where
+pub struct FdCanUSB<
+    #[cfg(feature = "serial2")] T = serial2::SerialPort,
+    #[cfg(not(feature = "serial2"))] T,
+    Buffer = Vec<u8>,
+> where
     T: std::io::Write + std::io::Read,
     Buffer: AsRef<[u8]> + AsMut<[u8]>,
 {
@@ -38,30 +77,251 @@ where
     read_len: usize,
     /// The number of leading bytes in the buffer that have already been used
     used_bytes: usize,
+    /// `rcv` frames read while waiting for an `OK`, returned by the next reads
+    pending: VecDeque<CanFdFrame>,
+    /// Counters of the frames and errors seen
+    stats: Stats,
+    /// Called with the raw bytes written to and read from the transport
+    raw_logger: Option<RawLogger>,
+    /// The maximum data length of a written frame, see [`FdCanUSB::set_max_data_len`]
+    max_data_len: usize,
+    /// The maximum length of a packet read, see [`FdCanUSB::set_max_line_len`]
+    max_line_len: usize,
+    /// Converts the timestamps of received frames into host times
+    clock: DeviceClock,
+    /// The `brs`, `fd_can_frame` and `remote_frame` flags of written frames that leave them as `None`
+    default_flags: (Option<bool>, Option<bool>, Option<bool>),
+    /// The line ending of written commands, see [`FdCanUSB::set_line_ending`]
+    line_ending: LineEnding,
+    /// The current time for timeouts, see [`FdCanUSB::set_clock`]
+    ///
+    /// A trait object rather than a type parameter, so a clock that is only swapped in tests does not add a parameter
+    /// to `FdCanUSB` and every type that names it. It is only called a few times per read or write, so the cost is negligible next to the IO.
+    time: Box<dyn Clock>,
+    /// When the last line was written, see [`FdCanUSB::transfer_timed`]
+    last_write_at: Option<std::time::Instant>,
+    /// The most recently read bytes, see [`FdCanUSB::recent_bytes`]
+    recent: Vec<u8>,
+    /// The number of recently read bytes kept, see [`FdCanUSB::set_recent_bytes_len`]
+    recent_len: usize,
+    /// The minimum time between writes, see [`FdCanUSB::set_min_interframe_gap`]
+    min_interframe_gap: Duration,
+    /// The lines of the last write, to recognise an echo of them
+    last_written: Vec<u8>,
+    /// The number of `OK`s not yet read for frames written without waiting for them, see [`FdCanUSB::pending_acks`]
+    pending_acks: usize,
+    /// The number of bytes at the end of `last_written` not yet written by [`FdCanUSB::try_write`]
+    unsent: usize,
+    /// Reused to encode frames written with [`FdCanUSB::write_encoded_into_scratch`]
+    scratch: Vec<u8>,
+    /// Whether each frame written and read is logged, see [`FdCanUSB::set_log_frames`]
+    log_frames: bool,
+    /// The last startup banner read, see [`FdCanUSB::banner`]
+    banner: Option<String>,
+    /// Checks received timestamps move forward, see [`FdCanUSB::set_timestamp_regression_handler`]
+    timestamp_check: Option<TimestampCheck>,
+    /// Sets the read timeout of the transport to the time left before a read's deadline, if the transport has one
+    set_read_timeout: Option<fn(&mut T, Duration) -> std::io::Result<()>>,
+    /// The path and settings the serial port was opened with, used to [reconnect](FdCanUSB::reconnect).
+    #[cfg(feature = "serial2")]
+    connection: Option<(std::path::PathBuf, serial2::Settings)>,
+}
+
+/// The USB vendor id of the mjbots FdCanUSB.
+#[cfg(feature = "serial2")]
+pub const USB_VID: u16 = 0x0483;
+/// The USB product id of the mjbots FdCanUSB.
+#[cfg(feature = "serial2")]
+pub const USB_PID: u16 = 0x5740;
+
+/// Reads the USB vendor and product id of the serial port at `path` from sysfs.
+///
+/// Returns `Ok(None)` if the port is not a USB device.
+#[cfg(all(feature = "serial2", target_os = "linux"))]
+fn usb_id(path: &std::path::Path) -> std::io::Result<Option<(u16, u16)>> {
+    let Some(name) = path.file_name() else {
+        return Ok(None);
+    };
+    let device = match std::fs::canonicalize(
+        std::path::Path::new("/sys/class/tty")
+            .join(name)
+            .join("device"),
+    ) {
+        Ok(device) => device,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // The tty belongs to a USB interface, whose parent holds the ids of the USB device
+    for dir in device.ancestors() {
+        let read_id = |file| -> std::io::Result<u16> {
+            let id = std::fs::read_to_string(dir.join(file))?;
+            u16::from_str_radix(id.trim(), 16)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        };
+        if dir.join("idVendor").exists() {
+            return Ok(Some((read_id("idVendor")?, read_id("idProduct")?)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(all(feature = "serial2", not(target_os = "linux")))]
+fn usb_id(_path: &std::path::Path) -> std::io::Result<Option<(u16, u16)>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "finding serial ports by USB id is only supported on Linux",
+    ))
 }
 
 #[cfg(feature = "serial2")]
 impl FdCanUSB<serial2::SerialPort, Vec<u8>> {
     /// For convenience, we provide a [`FdCanUSB`] implementation for [`serial2::SerialPort`].
     /// Enable with the `serial2` feature.
-
+    ///
+    /// `serial_settings` is anything implementing [`serial2::IntoSettings`], e.g. [`serial2::KeepSettings`],
+    /// a baud rate, or a closure which configures the [`serial2::Settings`].
+    /// The settings are kept, and applied again by [`FdCanUSB::reconnect`].
+    ///
+    /// Any data the port has already received is discarded, use [`FdCanUSB::open_keep_buffers`] to keep it.
+    /// ```no_run
+    /// use fdcanusb::{FdCanUSB, serial2};
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// let fdcanusb = FdCanUSB::open("/dev/fdcanusb", |mut settings: serial2::Settings| {
+    ///     settings.set_raw();
+    ///     settings.set_baud_rate(115_200)?;
+    ///     settings.set_parity(serial2::Parity::None);
+    ///     Ok(settings)
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn open<P: AsRef<std::path::Path>>(
         path: P,
         serial_settings: impl serial2::IntoSettings,
     ) -> std::io::Result<Self> {
-        let mut transport = serial2::SerialPort::open(path, serial_settings)?;
+        Self::open_with(path.as_ref(), serial_settings, true)
+    }
+
+    /// Open the serial port like [`FdCanUSB::open`], but keep any data the port has already received.
+    ///
+    /// This is for handing the port over between processes, e.g. when a supervisor restarts a daemon,
+    /// so that frames received in between, such as a burst of telemetry, are not lost.
+    /// The trade-off is that the data may start part way through a line, left by the previous process,
+    /// so the first read can fail with [`ReadError::LostSync`] or a parse error; see [`FdCanUSB::resync`] to recover.
+    pub fn open_keep_buffers<P: AsRef<std::path::Path>>(
+        path: P,
+        serial_settings: impl serial2::IntoSettings,
+    ) -> std::io::Result<Self> {
+        Self::open_with(path.as_ref(), serial_settings, false)
+    }
+
+    /// Open the serial port, discarding any data it has already received if `discard` is set.
+    fn open_with(
+        path: &std::path::Path,
+        serial_settings: impl serial2::IntoSettings,
+        discard: bool,
+    ) -> std::io::Result<Self> {
+        let path = path.to_path_buf();
+        let mut transport = serial2::SerialPort::open(&path, serial_settings)?;
+        let settings = transport.get_configuration()?;
         transport.set_read_timeout(std::time::Duration::from_millis(100))?;
         transport.flush()?;
-        transport.discard_buffers()?;
-        Ok(Self::new(transport))
+        if discard {
+            transport.discard_buffers()?;
+        }
+        let mut fdcanusb = Self::new(transport);
+        fdcanusb.set_read_timeout = Some(serial2::SerialPort::set_read_timeout);
+        fdcanusb.connection = Some((path, settings));
+        Ok(fdcanusb)
+    }
+
+    /// Open the first serial port of the USB device with the given vendor and product id, see [`FdCanUSB::open`].
+    ///
+    /// This finds the FdCanUSB without a fixed path like `/dev/ttyACM0`, which can change when it is re-plugged.
+    /// Returns a `NotFound` error if no port matches, or an `InvalidInput` error if more than one does.
+    ///
+    /// USB ids are read from sysfs, so this is only supported on Linux; other platforms return an `Unsupported` error.
+    pub fn open_by_usb_id(
+        vid: u16,
+        pid: u16,
+        serial_settings: impl serial2::IntoSettings,
+    ) -> std::io::Result<Self> {
+        let mut ports = Vec::new();
+        for path in serial2::SerialPort::available_ports()? {
+            match usb_id(&path) {
+                Ok(Some(id)) if id == (vid, pid) => ports.push(path),
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Err(e),
+                // A port that is not a USB device, or whose ids can not be read, is not the FdCanUSB
+                _ => {}
+            }
+        }
+        let mut ports = ports.into_iter();
+        match (ports.next(), ports.next()) {
+            (Some(path), None) => Self::open(path, serial_settings),
+            (None, _) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no serial port found for USB device {vid:04x}:{pid:04x}"),
+            )),
+            (Some(_), Some(_)) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("more than one serial port found for USB device {vid:04x}:{pid:04x}"),
+            )),
+        }
+    }
+
+    /// Open the FdCanUSB by its USB id, [`USB_VID`]:[`USB_PID`], keeping the serial port settings.
+    ///
+    /// See [`FdCanUSB::open_by_usb_id`].
+    pub fn open_default() -> std::io::Result<Self> {
+        Self::open_by_usb_id(USB_VID, USB_PID, serial2::KeepSettings)
+    }
+}
+
+#[cfg(feature = "serial2")]
+impl<Buffer> FdCanUSB<serial2::SerialPort, Buffer>
+where
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Re-open the serial port, using the path and settings it was originally opened with.
+    ///
+    /// This can be used to recover after the FdCanUSB is disconnected and every operation fails with an `Io` error.
+    /// Any frames in flight, or in the read buffer, are lost, so the caller should retry the last operation.
+    ///
+    /// Only available if the [`FdCanUSB`] was created with [`FdCanUSB::open`].
+    pub fn reconnect(&mut self) -> std::io::Result<()> {
+        let (path, settings) = self.connection.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "FdCanUSB was not opened from a path",
+            )
+        })?;
+        let mut transport = serial2::SerialPort::open(path, serial2::KeepSettings)?;
+        transport.set_configuration(settings)?;
+        transport.set_read_timeout(std::time::Duration::from_millis(100))?;
+        self.transport = transport;
+        self.read_len = 0;
+        self.used_bytes = 0;
+        self.unsent = 0;
+        self.pending_acks = 0;
+        self.flush()
     }
 
     /// Flush the FdCanUSB.
     /// This can be important to do when initializing the FdCanUSB, as any data in the buffer can cause lost sync issues.
     pub fn flush(&mut self) -> std::io::Result<()> {
         self.transport.flush()?;
+        self.pending_acks = 0;
         self.transport.discard_buffers()
     }
+
+    /// Flush the FdCanUSB and close the serial port.
+    ///
+    /// Any `OK` or `rcv` packets still in flight are discarded,
+    /// so that the next program to open the port does not inherit them and lose sync.
+    /// Prefer this over dropping the [`FdCanUSB`], as dropping can not report errors and leaves pending data in the port.
+    pub fn close(mut self) -> std::io::Result<()> {
+        self.flush()
+    }
 }
 
 impl<T> FdCanUSB<T, Vec<u8>>
@@ -74,6 +334,29 @@ where
     }
 }
 
+impl<T, const N: usize> FdCanUSB<T, [u8; N]>
+where
+    T: std::io::Write + std::io::Read,
+{
+    /// Create a new [FdCanUSB] instance backed by a fixed-size array, avoiding a heap allocation for the buffer.
+    ///
+    /// `N` is also the default maximum line length, so it must be large enough for the longest
+    /// `rcv` line the device can send (around 160 bytes for a 64 byte CAN-FD frame).
+    ///
+    /// ```no_run
+    /// # use fdcanusb::FdCanUSB;
+    /// # fn example<T: std::io::Read + std::io::Write>(transport: T) {
+    /// let fdcanusb = FdCanUSB::<_, [u8; 256]>::new_with_array(transport);
+    /// // Equivalent to:
+    /// # let transport = fdcanusb.into_inner();
+    /// let fdcanusb = FdCanUSB::new_with_buffer(transport, [0u8; 256]);
+    /// # }
+    /// ```
+    pub fn new_with_array(transport: T) -> Self {
+        Self::new_with_buffer(transport, [0; N])
+    }
+}
+
 impl<T, Buffer> FdCanUSB<T, Buffer>
 where
     T: std::io::Write + std::io::Read,
@@ -81,135 +364,2609 @@ where
 {
     /// Create a new [FdCanUSB] instance, with a given transport and buffer.
     pub fn new_with_buffer(transport: T, buffer: Buffer) -> Self {
+        let max_line_len = buffer.as_ref().len();
         FdCanUSB {
             transport,
             buffer,
             read_len: 0,
             used_bytes: 0,
+            pending: VecDeque::new(),
+            stats: Stats::default(),
+            raw_logger: None,
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+            max_line_len,
+            default_flags: (None, None, None),
+            clock: DeviceClock::default(),
+            line_ending: LineEnding::default(),
+            time: Box::new(SystemClock),
+            last_write_at: None,
+            min_interframe_gap: Duration::ZERO,
+            recent: Vec::new(),
+            recent_len: DEFAULT_RECENT_BYTES_LEN,
+            last_written: Vec::new(),
+            pending_acks: 0,
+            unsent: 0,
+            scratch: Vec::new(),
+            log_frames: true,
+            banner: None,
+            timestamp_check: None,
+            set_read_timeout: None,
+            #[cfg(feature = "serial2")]
+            connection: None,
         }
     }
 
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Get a mutable reference to the underlying transport.
+    ///
+    /// Reading from or writing to the transport directly can cause lost sync issues.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Consume the [FdCanUSB], returning the underlying transport.
+    ///
+    /// Any data in the read buffer is lost.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
     /// Transfer a single frame.
     /// If `response` is `true`, the function will wait for a response frame.
     /// Otherwise, it will return `None`.
+    ///
+    /// Pass [`ExpectResponse::FromId`] to wait for a response only if the frame requests one,
+    /// see [`CanFdFrame::expects_response`].
     pub fn transfer_single(
         &mut self,
         frame: CanFdFrame,
-        response: bool,
+        response: impl Into<ExpectResponse>,
+    ) -> Result<Option<CanFdFrame>, TransferError> {
+        self.transfer_single_timeout(frame, response, DEFAULT_TIMEOUT)
+    }
+
+    /// Transfer a single frame, waiting up to `timeout` for the response frame.
+    ///
+    /// If no part of a response arrives before the timeout, [`TransferError::NoResponse`] is returned.
+    /// See [`FdCanUSB::transfer_single`].
+    pub fn transfer_single_timeout(
+        &mut self,
+        frame: CanFdFrame,
+        response: impl Into<ExpectResponse>,
+        timeout: Duration,
     ) -> Result<Option<CanFdFrame>, TransferError> {
+        let response = match response.into() {
+            ExpectResponse::Yes => true,
+            ExpectResponse::No => false,
+            ExpectResponse::FromId => frame.expects_response(),
+        };
         self.write(frame)?;
         if response {
-            Ok(Some(self.read()?))
+            match self.read_timeout(timeout) {
+                Ok(response) => Ok(Some(response)),
+                // Bytes left in the buffer are the start of a line which did not finish in time
+                Err(ReadError::Timeout(source)) if self.read_len == self.used_bytes => {
+                    Err(TransferError::NoResponse { source })
+                }
+                Err(e) => Err(e.into()),
+            }
         } else {
             Ok(None)
         }
     }
 
+    /// Transfer a single frame like [`FdCanUSB::transfer_single`], also returning the time since the frame was written.
+    ///
+    /// The time is measured from just before the frame is written to the transport until the response is read,
+    /// or the `OK` if no response is expected, so it includes the USB round trip as well as the time on the bus.
+    /// A response which was already queued before the frame was written is returned immediately, with a short time.
+    pub fn transfer_timed(
+        &mut self,
+        frame: CanFdFrame,
+        response: impl Into<ExpectResponse>,
+    ) -> Result<(Option<CanFdFrame>, Duration), TransferError> {
+        let response = self.transfer_single(frame, response)?;
+        let now = self.now();
+        let elapsed = self
+            .last_write_at
+            .map_or(Duration::ZERO, |written| now.duration_since(written));
+        Ok((response, elapsed))
+    }
+
+    /// Transfer a frame, and read exactly `expected_responses` response frames.
+    ///
+    /// Unlike [`FdCanUSB::transfer_single`], which reads at most one response, this is for commands that produce
+    /// several `rcv` frames, e.g. a moteus query of registers spanning multiple frames.
+    /// Responses are returned in the order they were received, including any already queued.
+    ///
+    /// If fewer responses arrive within the default timeout, [`TransferError::Incomplete`] is returned
+    /// with the responses that were received.
+    pub fn transfer(
+        &mut self,
+        frame: CanFdFrame,
+        expected_responses: usize,
+    ) -> Result<Vec<CanFdFrame>, TransferError> {
+        self.write(frame)?;
+        let mut responses = Vec::with_capacity(expected_responses);
+        while responses.len() < expected_responses {
+            match self.read() {
+                Ok(response) => responses.push(response),
+                Err(ReadError::Timeout(_)) => {
+                    return Err(TransferError::Incomplete {
+                        responses,
+                        expected: expected_responses,
+                    })
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(responses)
+    }
+
+    /// Transfer a single frame, retrying up to `retries` times if the transfer fails with a timeout or lost sync.
+    ///
+    /// Before each retry the transport is flushed, any queued frames are discarded,
+    /// and the input is read and discarded until it is quiet for 10ms,
+    /// so that a late `OK` or `rcv` from the failed attempt is not mistaken for the reply to the retry.
+    /// An `OK` discarded this way is counted against [`FdCanUSB::pending_acks`], so earlier acks still in flight are still read.
+    /// Other errors, see [`TransferError::is_retryable`], are returned immediately.
+    ///
+    /// See [`FdCanUSB::transfer_single`].
+    pub fn transfer_with_retries(
+        &mut self,
+        frame: CanFdFrame,
+        response: impl Into<ExpectResponse>,
+        retries: usize,
+    ) -> Result<Option<CanFdFrame>, TransferError> {
+        let response = response.into();
+        let mut attempt = 0;
+        loop {
+            match self.transfer_single(frame.clone(), response) {
+                Err(e) if e.is_retryable() && attempt < retries => {
+                    attempt += 1;
+                    debug!("Retrying transfer ({attempt}/{retries}) after: {e}");
+                    self.discard_buffered()?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Transfer multiple frames, sending them back-to-back before reading any responses.
+    ///
+    /// Every frame is written before the first acknowledgment is read, which avoids a round trip per frame.
+    /// The [FdCanUSB] replies with an `OK` for each frame, in the order the frames were written,
+    /// and `responses_per_frame` `rcv` frames are then expected for each frame sent.
+    ///
+    /// Responses are returned in the order they were received, which may not match the order the frames were sent
+    /// if multiple devices are replying.
+    ///
+    /// If a frame can not be written or is not acknowledged, the error is returned as [`TransferError::Frame`]
+    /// with the `index` of the offending frame, and the `OK`s of the frames written after it are counted in
+    /// [`FdCanUSB::pending_acks`], to be read later.
+    /// An error reading the responses, once every frame is acknowledged, is returned without an index,
+    /// as the responses may arrive in any order. Any responses already received are discarded.
+    pub fn transfer_many(
+        &mut self,
+        frames: impl IntoIterator<Item = CanFdFrame>,
+        responses_per_frame: usize,
+    ) -> Result<Vec<CanFdFrame>, TransferError> {
+        let mut sent = 0;
+        for frame in frames {
+            let result = match self.encode(frame) {
+                Ok(frame) => self.write_frame(&frame).map_err(TransferError::from),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                // The frames already written are still acknowledged
+                self.pending_acks += sent;
+                return Err(TransferError::Frame {
+                    index: sent,
+                    source: Box::new(e),
+                });
+            }
+            sent += 1;
+        }
+        self.compact();
+
+        let expected = sent * responses_per_frame;
+        let log_frames = self.log_frames;
+        let mut acks = 0;
+        let mut responses = Vec::with_capacity(expected);
+        while acks < sent || responses.len() < expected {
+            let (index, awaiting_ack) = (acks, acks < sent);
+            let frame_error = move |e: ReadError| {
+                if awaiting_ack {
+                    TransferError::Frame {
+                        index,
+                        source: Box::new(e.into()),
+                    }
+                } else {
+                    e.into()
+                }
+            };
+            let packet = match self.read_packet(DEFAULT_TIMEOUT) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    self.stats.record_error(&e);
+                    if awaiting_ack {
+                        self.pending_acks += sent - acks - 1;
+                    }
+                    return Err(frame_error(e));
+                }
+            };
+            let result = if acks < sent && packet.starts_with(b"OK") {
+                acks += 1;
+                Ok(())
+            } else if packet.starts_with(b"rcv") {
+                parse_rcv(packet, log_frames).map(|response| {
+                    self.check_timestamp(&response);
+                    self.stats.frames_received += 1;
+                    responses.push(response);
+                })
+            } else {
+                Err(unexpected_packet(
+                    if acks < sent { "OK" } else { "rcv" },
+                    packet,
+                ))
+            };
+            if let Err(e) = result {
+                self.stats.record_error(&e);
+                if awaiting_ack {
+                    self.pending_acks += sent - acks - 1;
+                }
+                return Err(frame_error(e));
+            }
+        }
+        Ok(responses)
+    }
+
     /// Write a frame to the FdCanUSB
     pub fn write(&mut self, frame: CanFdFrame) -> Result<(), TransferError> {
-        let frame: FdCanUSBFrame = frame.into();
-        self.write_frame(frame)?;
-        self.read_len = 0;
-        self.used_bytes = 0;
-        self.read_ok()?;
+        self.write_returning(frame)?;
         Ok(())
     }
 
-    /// Read a response frame from the [FdCanUSB].
-    /// Responses are logged at the `trace` level by default.
-    pub fn read(&mut self) -> Result<CanFdFrame, ReadError> {
-        let packet = self.read_newline()?;
-        let packet = &self.buffer.as_ref()[self.used_bytes..packet];
-        self.used_bytes += packet.len();
-        if packet.starts_with(b"rcv") {
-            let response = std::str::from_utf8(packet)?;
-            debug!("< {:?}", response);
-            let response = FdCanUSBFrame::from(response);
-            let response = response.try_into()?;
-            Ok(response)
-        } else {
-            Err(ReadError::LostSync {
-                expected: "rcv".to_string(),
-                received: String::from_utf8_lossy(packet).to_string(),
+    /// Write a frame to the FdCanUSB, returning the exact [`FdCanUSBFrame`] that was written.
+    ///
+    /// This is useful for logging the command sent to the device, without encoding the frame twice.
+    pub fn write_returning(&mut self, frame: CanFdFrame) -> Result<FdCanUSBFrame, TransferError> {
+        let frame = self.encode(frame)?;
+        self.write_acked(&frame)?;
+        Ok(frame)
+    }
+
+    /// Write a frame to the FdCanUSB, encoding it into a buffer owned by the [`FdCanUSB`] rather than allocating.
+    ///
+    /// The frame is borrowed, so the same frame can be written again, e.g. to retry, without cloning it.
+    /// This is otherwise the same as [`FdCanUSB::write`].
+    pub fn write_encoded_into_scratch(&mut self, frame: &CanFdFrame) -> Result<(), TransferError> {
+        self.check_data_len(frame, frame.fd_can_frame.or(self.default_flags.1))?;
+        frame.validate_with_defaults(self.default_flags)?;
+        // The buffer is only grown the first time, as every frame fits in `MAX_ENCODED_LEN` bytes
+        let mut scratch = std::mem::take(&mut self.scratch);
+        if scratch.len() < CanFdFrame::MAX_ENCODED_LEN {
+            scratch.resize(CanFdFrame::MAX_ENCODED_LEN, 0);
+        }
+        let result = self.write_scratch(frame, &mut scratch);
+        self.scratch = scratch;
+        result
+    }
+
+    /// Write a pre-encoded [`FdCanUSBFrame`] to the FdCanUSB, and wait for its `OK`.
+    ///
+    /// This can be used to send custom commands, e.g. created with [`FdCanUSBFrame::from`],
+    /// while keeping in sync with the acknowledgment. The frame must end with a newline.
+    /// **No** validation is performed, use [`str::parse`] to check the frame first.
+    pub fn write_raw(&mut self, frame: FdCanUSBFrame) -> Result<(), TransferError> {
+        self.write_acked(&frame)?;
+        Ok(())
+    }
+
+    /// Write a frame to the FdCanUSB, returning the [`Ack`] with any fields the firmware sends after the `OK`.
+    ///
+    /// Some firmware reports flow control information this way, e.g. how full its transmit queue is,
+    /// which can be used to rate limit bulk sends. This is otherwise the same as [`FdCanUSB::write`].
+    pub fn write_returning_ack(&mut self, frame: CanFdFrame) -> Result<Ack, TransferError> {
+        let frame = self.encode(frame)?;
+        self.write_acked(&frame)
+    }
+
+    /// Read every configuration key and value from the [FdCanUSB], using `conf enumerate`.
+    ///
+    /// The pairs are returned in the order the device lists them.
+    /// Any `rcv` frames received during the enumeration are queued for later reads.
+    pub fn conf_enumerate(&mut self) -> Result<Vec<(String, String)>, TransferError> {
+        let pairs = self
+            .command_lines("conf enumerate")?
+            .into_iter()
+            .map(|line| {
+                let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+                (key.to_string(), value.to_string())
             })
+            .collect();
+        Ok(pairs)
+    }
+
+    /// Read a configuration value of the [FdCanUSB], using `conf get`.
+    pub fn conf_get(&mut self, key: &str) -> Result<String, TransferError> {
+        let lines = self.command_lines(&format!("conf get {key}"))?;
+        match lines.into_iter().next() {
+            Some(value) => Ok(value),
+            None => {
+                let e = ReadError::from(ParseError::UnexpectedEOL {
+                    expected: format!("{key} value"),
+                    offset: None,
+                });
+                self.stats.record_error(&e);
+                Err(e.into())
+            }
         }
     }
 
-    /// Write a frame to the FdCanUSB
+    /// Set a configuration value of the [FdCanUSB], using `conf set`.
     ///
-    /// Frames are logged at the `debug` level by default.
-    fn write_frame(&mut self, frame: FdCanUSBFrame) -> Result<(), WriteError> {
-        debug!("> {:?}", frame);
-        self.transport.write_all(frame.as_bytes())?;
+    /// The value is only used until the FdCanUSB is reset, unless it is saved with `conf write`.
+    pub fn conf_set(&mut self, key: &str, value: &str) -> Result<(), TransferError> {
+        self.command_lines(&format!("conf set {key} {value}"))?;
         Ok(())
     }
 
-    /// Reads bytes into the buffer and returns the end pos of one packet.
-    /// Packets are seperated by `/r/n`.
-    fn read_newline(&mut self) -> Result<usize, std::io::Error> {
-        let buffer = self.buffer.as_mut();
-        let timeout = std::time::Instant::now() + std::time::Duration::from_millis(500);
+    /// Check the [FdCanUSB] responds, by reading its arbitration bitrate with `conf get`.
+    ///
+    /// A serial port can open while the device behind it is unplugged or stuck,
+    /// so calling this after [`FdCanUSB::open`] turns a dead port into a timeout at startup, rather than on the first transfer.
+    /// An `ERR` reply still shows the device is responding, so it is not an error.
+    pub fn ping(&mut self) -> Result<(), TransferError> {
+        match self.conf_get(ARBITRATION_BITRATE_KEY) {
+            Ok(_) | Err(TransferError::Read(ReadError::DeviceError { .. })) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set the arbitration and data bitrates of the CAN bus, and save them to the [FdCanUSB]'s flash.
+    ///
+    /// The arbitration bitrate must be between 1 bps and 1 Mbps,
+    /// and the data bitrate at most 8 Mbps, and at least the arbitration bitrate.
+    /// Other rates return [`TransferError::UnsupportedConfig`] without writing anything.
+    ///
+    /// Both rates are read back before they are saved, and [`TransferError::ConfigMismatch`] is returned if either differs,
+    /// e.g. if the firmware can not reach a rate in the range.
+    pub fn set_bitrate(
+        &mut self,
+        arbitration_bps: u32,
+        data_bps: u32,
+    ) -> Result<(), TransferError> {
+        let unsupported = |key: &str, value: u32| TransferError::UnsupportedConfig {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        if !(1..=MAX_ARBITRATION_BITRATE).contains(&arbitration_bps) {
+            return Err(unsupported(ARBITRATION_BITRATE_KEY, arbitration_bps));
+        }
+        if !(arbitration_bps..=MAX_DATA_BITRATE).contains(&data_bps) {
+            return Err(unsupported(DATA_BITRATE_KEY, data_bps));
+        }
+        let rates = [
+            (ARBITRATION_BITRATE_KEY, arbitration_bps),
+            (DATA_BITRATE_KEY, data_bps),
+        ];
+        for (key, bps) in rates {
+            self.conf_set(key, &bps.to_string())?;
+        }
+        for (key, bps) in rates {
+            let received = self.conf_get(key)?;
+            if received.parse() != Ok(bps) {
+                return Err(TransferError::ConfigMismatch {
+                    key: key.to_string(),
+                    expected: bps.to_string(),
+                    received,
+                });
+            }
+        }
+        self.command_lines("conf write")?;
+        Ok(())
+    }
+
+    /// Read the firmware version of the [FdCanUSB], using the `version` console command.
+    ///
+    /// The reply is returned as sent by the device, with multiple lines joined by `\n`,
+    /// as the format differs between firmware revisions.
+    /// Firmware that does not support the command replies with `ERR`, which is returned as [`ReadError::DeviceError`].
+    pub fn version(&mut self) -> Result<String, TransferError> {
+        Ok(self.command_lines("version")?.join("\n"))
+    }
+
+    /// Wait for the [FdCanUSB] to send its startup banner after a reset, returning the banner.
+    ///
+    /// The FdCanUSB sends a line with its firmware name and version when it starts, before it accepts commands,
+    /// which is taken to be the first line starting with `fdcanusb`, ignoring case.
+    /// `rcv` frames read before the banner are queued for the next reads, and any other lines are discarded.
+    /// Frames written without waiting for their `OK` are forgotten, see [`FdCanUSB::pending_acks`],
+    /// as the device lost them when it reset.
+    /// Returns [`ReadError::Timeout`] if no banner arrives within `timeout`, e.g. if the device did not reset.
+    ///
+    /// Only this method looks for the banner, a read which finds one returns [`ReadError::LostSync`].
+    pub fn wait_for_ready(&mut self, timeout: Duration) -> Result<String, TransferError> {
+        let deadline = self.now() + timeout;
+        let log_frames = self.log_frames;
         loop {
-            if let Some(pos) = buffer[self.used_bytes..self.read_len]
-                .iter()
-                .position(|&c| c == b'\n')
-            {
-                trace!(
-                    "raw packet {:?}",
-                    &buffer[self.used_bytes..self.used_bytes + pos + 1]
-                );
-                return Ok(self.used_bytes + pos + 1);
+            let timeout = deadline.saturating_duration_since(self.now());
+            let (start, end) = self
+                .read_packet_range(timeout)
+                .inspect_err(|e| self.stats.record_error(e))?;
+            let packet = &self.buffer.as_ref()[start..end];
+            if is_banner(packet) {
+                let banner = String::from_utf8_lossy(packet).into_owned();
+                self.pending_acks = 0;
+                self.banner = Some(banner.clone());
+                return Ok(banner);
             }
-            if std::time::Instant::now() > timeout {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::TimedOut,
-                    "Timed out waiting for newline",
-                ));
+            if packet.starts_with(b"rcv") {
+                if let Ok(response) = parse_rcv(packet, log_frames) {
+                    self.check_timestamp(&response);
+                    self.pending.push_back(response);
+                    continue;
+                }
             }
-            let read_num = self.transport.read(&mut buffer[self.read_len..])?;
-            trace!(
-                "read {} {:?}",
-                read_num,
-                &buffer[self.read_len..self.read_len + read_num]
+            debug!(
+                "discarded {:?} before banner",
+                String::from_utf8_lossy(packet)
             );
-            self.read_len += read_num;
         }
     }
 
-    /// The [FdCanUSB] responds with `OK` after a correct frame is parsed.
-    /// `read_ok` waits for this response, and returns an error if it is not received.
-    fn read_ok(&mut self) -> Result<(), ReadError> {
-        let packet = self.read_newline()?;
-        let packet = &self.buffer.as_ref()[self.used_bytes..packet];
-        self.used_bytes += packet.len();
-        if packet.starts_with(b"OK") {
-            Ok(())
-        } else {
-            Err(ReadError::LostSync {
-                expected: "OK".to_string(),
-                received: String::from_utf8_lossy(packet).to_string(),
-            })
+    /// The last startup banner read from the [FdCanUSB], with its firmware name and version.
+    ///
+    /// The banner is sent when the device resets, so it is only read if the [FdCanUSB] was opened before a reset,
+    /// by [`FdCanUSB::wait_for_ready`].
+    /// See [`FdCanUSB::version`] to ask the device for its version instead.
+    pub fn banner(&self) -> Option<&str> {
+        self.banner.as_deref()
+    }
+
+    /// Write a frame to the FdCanUSB without waiting for its `OK`.
+    ///
+    /// This avoids blocking on the acknowledgment, which is useful when streaming commands at a high rate.
+    ///
+    /// The `OK` is counted in [`FdCanUSB::pending_acks`], and read later:
+    /// the next reads skip the `OK`s of these frames before the `rcv` frames after them,
+    /// and the next [`FdCanUSB::write`] reads them before its own, so many frames can be pipelined.
+    /// An `ERR` for one of these frames is returned by the read which reads it,
+    /// or, if it is read by a write waiting for its own `OK`, is counted in the [`Stats`] and logged rather than returned for the wrong frame.
+    pub fn write_no_ack(&mut self, frame: CanFdFrame) -> Result<(), TransferError> {
+        let frame = self.encode(frame)?;
+        self.write_frame(&frame)?;
+        self.pending_acks += 1;
+        Ok(())
+    }
+
+    /// The number of `OK`s not yet read for frames written with [`FdCanUSB::write_no_ack`] or [`FdCanUSB::try_write`].
+    pub fn pending_acks(&self) -> usize {
+        self.pending_acks
+    }
+
+    /// Write a frame to the [FdCanUSB] without blocking, for a transport in non-blocking mode, e.g. one polled with `epoll`.
+    ///
+    /// Returns [`WriteStatus::WouldBlock`] if none of the frame could be written, so it should be passed again once the transport is writable.
+    /// A `TimedOut` error from the transport, e.g. a serial port with a short write timeout, is also taken as `WouldBlock`.
+    /// Otherwise the frame is taken and [`WriteStatus::Sent`] is returned, even if only part of it was written:
+    /// the rest is written before any later frame, by the next `try_write` or [`FdCanUSB::try_write_unsent`], or by a blocking write.
+    ///
+    /// Like [`FdCanUSB::write_no_ack`], the `OK` is read later, see [`FdCanUSB::pending_acks`].
+    /// Rather than sleeping, `WouldBlock` is also returned until the [minimum inter-frame gap](FdCanUSB::set_min_interframe_gap) has passed.
+    pub fn try_write(&mut self, frame: CanFdFrame) -> Result<WriteStatus, TransferError> {
+        if self.try_write_unsent()? == WriteStatus::WouldBlock
+            || !self.interframe_gap_remaining().is_zero()
+        {
+            return Ok(WriteStatus::WouldBlock);
+        }
+        let frame = self.encode(frame)?;
+        let mut line = Vec::new();
+        self.line_ending.push_line(&mut line, frame.as_bytes());
+        match self.transport.write(&line) {
+            Ok(0) => {
+                return Err(
+                    WriteError::from(std::io::Error::from(std::io::ErrorKind::WriteZero)).into(),
+                )
+            }
+            Ok(n) => self.unsent = line.len() - n,
+            // Nothing was written, so the frame can be passed again
+            Err(e) if is_would_block(&e) || e.kind() == std::io::ErrorKind::Interrupted => {
+                return Ok(WriteStatus::WouldBlock)
+            }
+            Err(e) => return Err(WriteError::from(e).into()),
+        }
+        if self.log_frames {
+            log_tx(&line);
         }
+        self.last_write_at = Some(self.now());
+        if let Some(logger) = &mut self.raw_logger {
+            (logger.0)(Direction::Tx, &line);
+        }
+        self.stats.frames_sent += 1;
+        self.pending_acks += 1;
+        self.last_written = line;
+        self.try_write_unsent()?;
+        Ok(WriteStatus::Sent)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Write the rest of a frame partly written by [`FdCanUSB::try_write`], without blocking.
+    ///
+    /// Returns [`WriteStatus::Sent`] once nothing is left to write, or [`WriteStatus::WouldBlock`] if the transport is full.
+    pub fn try_write_unsent(&mut self) -> Result<WriteStatus, WriteError> {
+        while self.unsent > 0 {
+            let start = self.last_written.len() - self.unsent;
+            match self.transport.write(&self.last_written[start..]) {
+                Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into()),
+                Ok(n) => self.unsent -= n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) if is_would_block(&e) => return Ok(WriteStatus::WouldBlock),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(WriteStatus::Sent)
+    }
 
-    #[test]
-    fn test_fdcanusb() {
-        let mut fdcanusb = FdCanUSB::open("/dev/fdcanusb", serial2::KeepSettings)
-            .expect("Failed to open fdcanusb");
-        let frame = FdCanUSBFrame::from(
-            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050 b\n",
-        );
-        // let frame= FdCanUSBFrame::from("can send 8001 01000011001F01130D505050 b");
+    /// Write the rest of a frame partly written by [`FdCanUSB::try_write`], blocking until it is written.
+    fn write_unsent(&mut self) -> std::io::Result<()> {
+        if self.unsent > 0 {
+            let start = self.last_written.len() - self.unsent;
+            self.transport.write_all(&self.last_written[start..])?;
+            self.unsent = 0;
+        }
+        Ok(())
+    }
 
-        fdcanusb.write_frame(frame).expect("Failed to write frame");
-        fdcanusb.read_ok().expect("Failed to read ok");
-        let respsonse = fdcanusb.read();
-        dbg!(&respsonse);
-        assert!(respsonse.is_ok());
+    /// Read a response frame from the [FdCanUSB].
+    /// Responses are logged at the `trace` level by default.
+    ///
+    /// Packets are parsed as bytes rather than validated as UTF-8 first,
+    /// so invalid UTF-8 in a token that is not parsed, such as an unknown flag, is ignored,
+    /// and only a structural error in the `rcv` line, such as corrupt hex, fails the read.
+    pub fn read(&mut self) -> Result<CanFdFrame, ReadError> {
+        self.read_timeout(DEFAULT_TIMEOUT)
+    }
+
+    /// Read a response frame from the [FdCanUSB], waiting up to `timeout` for it to arrive.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<CanFdFrame, ReadError> {
+        let response = self
+            .read_rcv(timeout)
+            .inspect_err(|e| self.stats.record_error(e))?;
+        self.stats.frames_received += 1;
+        Ok(response)
+    }
+
+    /// Read the next `rcv` line from the [FdCanUSB], borrowing its bytes from the read buffer rather than parsing it.
+    ///
+    /// This avoids allocating a [`CanFdFrame`] when a frame is relayed verbatim, e.g. by a bridge to another interface.
+    /// The line terminator is removed, and a line which is not a `rcv` frame returns [`ReadError::LostSync`], like [`FdCanUSB::read`].
+    ///
+    /// The bytes borrow the `FdCanUSB` until they are dropped, as the next read may overwrite them.
+    /// Frames already queued by an earlier call, e.g. `rcv` frames received before an `OK`, are not returned,
+    /// use [`FdCanUSB::read`] or [`FdCanUSB::read_available`] for those.
+    pub fn read_into(&mut self) -> Result<&[u8], ReadError> {
+        let (start, end) = self
+            .read_packet_range(DEFAULT_TIMEOUT)
+            .inspect_err(|e| self.stats.record_error(e))?;
+        let packet = &self.buffer.as_ref()[start..end];
+        if !packet.starts_with(b"rcv") {
+            let e = unexpected_packet("rcv", packet);
+            self.stats.record_error(&e);
+            return Err(e);
+        }
+        self.stats.frames_received += 1;
+        Ok(&self.buffer.as_ref()[start..end])
+    }
+
+    /// Read the next line from the [FdCanUSB], without interpreting it.
+    ///
+    /// Any packet is returned, including `OK`, `ERR` and config replies, with the `\r\n` line terminator removed.
+    /// This is useful for forwarding everything the device sends, e.g. in a bridge.
+    ///
+    /// Frames already queued by an earlier call, e.g. `rcv` frames received before an `OK`, are not returned,
+    /// use [`FdCanUSB::read`] or [`FdCanUSB::read_available`] for those.
+    pub fn read_line_raw(&mut self) -> Result<Vec<u8>, ReadError> {
+        self.read_line_raw_timeout(DEFAULT_TIMEOUT)
+    }
+
+    /// Read the next line from the [FdCanUSB], waiting up to `timeout` for it to arrive.
+    ///
+    /// See [`FdCanUSB::read_line_raw`].
+    pub fn read_line_raw_timeout(&mut self, timeout: Duration) -> Result<Vec<u8>, ReadError> {
+        match self.read_packet(timeout) {
+            Ok(packet) => Ok(packet.to_vec()),
+            Err(e) => {
+                self.stats.record_error(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Set the maximum data length of frames written to the [FdCanUSB], which defaults to 64.
+    ///
+    /// Frames with more data are rejected with [`TransferError::InvalidFrameLength`] before they are written.
+    /// This catches configuration mistakes early, e.g. when the FdCanUSB is connected to a classic CAN bus,
+    /// where the limit should be set to 8.
+    pub fn set_max_data_len(&mut self, max_data_len: usize) {
+        self.max_data_len = max_data_len;
+    }
+
+    /// The maximum data length of frames written to the [FdCanUSB], see [`FdCanUSB::set_max_data_len`].
+    pub fn max_data_len(&self) -> usize {
+        self.max_data_len
+    }
+
+    /// The last bytes read from the transport, up to the length set with [`FdCanUSB::set_recent_bytes_len`], oldest first.
+    ///
+    /// These are the raw bytes, including any line terminators and bytes that were discarded,
+    /// so after a [`ReadError::LostSync`] they show what was read leading up to it, e.g. an `OK` in the stream of `rcv` frames.
+    pub fn recent_bytes(&self) -> &[u8] {
+        &self.recent[self.recent.len().saturating_sub(self.recent_len)..]
+    }
+
+    /// Set the number of recently read bytes kept for [`FdCanUSB::recent_bytes`], which defaults to 512.
+    ///
+    /// A length of 0 stops keeping them, and frees the bytes already kept.
+    pub fn set_recent_bytes_len(&mut self, len: usize) {
+        self.recent_len = len;
+        if len == 0 {
+            self.recent = Vec::new();
+        }
+    }
+
+    /// Set the minimum time between the starts of two writes to the [FdCanUSB], which defaults to zero.
+    ///
+    /// Some devices drop commands written back to back too quickly over USB, when their receive buffer overruns,
+    /// which shows up as writes timing out after working for a while.
+    /// Each write sleeps until the gap has passed since the last write, and a [`BatchWriter`] waits once before its whole batch.
+    pub fn set_min_interframe_gap(&mut self, gap: Duration) {
+        self.min_interframe_gap = gap;
+    }
+
+    /// The minimum time between writes, see [`FdCanUSB::set_min_interframe_gap`].
+    pub fn min_interframe_gap(&self) -> Duration {
+        self.min_interframe_gap
+    }
+
+    /// The time until the minimum inter-frame gap has passed since the last write.
+    fn interframe_gap_remaining(&self) -> Duration {
+        match self.last_write_at {
+            Some(written) => {
+                (written + self.min_interframe_gap).saturating_duration_since(self.now())
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Sleep on the [`Clock`] until the minimum inter-frame gap has passed since the last write.
+    fn wait_interframe_gap(&self) {
+        let remaining = self.interframe_gap_remaining();
+        if !remaining.is_zero() {
+            self.time.sleep(remaining);
+        }
+    }
+
+    /// Read frames continuously, passing each one to `f`, until `f` returns [`ControlFlow::Break`](std::ops::ControlFlow::Break).
+    ///
+    /// This is useful for consuming unsolicited frames, e.g. telemetry a moteus controller sends without being queried.
+    /// Any read error is returned, including a timeout if the stream stops.
+    pub fn stream_reads<F>(&mut self, mut f: F) -> Result<(), ReadError>
+    where
+        F: FnMut(CanFdFrame) -> std::ops::ControlFlow<()>,
+    {
+        loop {
+            if f(self.read()?).is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// An iterator over the frames received by the [FdCanUSB].
+    ///
+    /// The iterator ends when no frame arrives within the default timeout,
+    /// use [`Incoming::blocking`] to keep waiting instead.
+    /// ```no_run
+    /// # fn main() -> Result<(), std::io::Error> {
+    /// # let mut fdcanusb = fdcanusb::FdCanUSB::open("/dev/fdcanusb", serial2::KeepSettings)?;
+    /// for frame in fdcanusb.incoming() {
+    ///     println!("{:?}", frame);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn incoming(&mut self) -> Incoming<'_, T, Buffer> {
+        Incoming {
+            fdcanusb: self,
+            blocking: false,
+        }
+    }
+
+    /// Discard bytes until the next packet starts with `rcv` or `OK`, e.g. after a [`ReadError::LostSync`].
+    ///
+    /// The buffer is left positioned at the start of that packet, so it is returned by the next read.
+    /// Unlike flushing the port, frames that were already buffered or queued are kept.
+    /// Returns the number of bytes discarded, or an `Err` if no such packet arrives within the default timeout.
+    pub fn resync(&mut self) -> Result<usize, ReadError> {
+        let mut discarded = 0;
+        loop {
+            let end = self
+                .read_newline(DEFAULT_TIMEOUT)
+                .inspect_err(|e| self.stats.record_error(e))?;
+            let packet = &self.buffer.as_ref()[self.used_bytes..end];
+            if packet.starts_with(b"rcv") || packet.starts_with(b"OK") {
+                debug!("resync discarded {} bytes", discarded);
+                return Ok(discarded);
+            }
+            discarded += packet.len();
+            self.used_bytes = end;
+        }
+    }
+
+    /// The number of bytes read from the transport that have not been used yet, e.g. a partial packet.
+    pub fn buffered_len(&self) -> usize {
+        self.read_len - self.used_bytes
+    }
+
+    /// Move the unread bytes to the start of the buffer, so the whole buffer after them is free for the next read.
+    ///
+    /// This is done before each write, but can be done earlier to make room for a large read.
+    /// No buffered data is lost.
+    pub fn compact(&mut self) {
+        self.buffer
+            .as_mut()
+            .copy_within(self.used_bytes..self.read_len, 0);
+        self.read_len -= self.used_bytes;
+        self.used_bytes = 0;
+    }
+
+    /// Set the flags used for written frames which leave a flag as `None`.
+    ///
+    /// A flag set on the frame always takes precedence over the default,
+    /// and a default of `None` leaves the flag to the FdCanUSB, as before.
+    /// Whether the id is extended is part of the frame's [`CanId`](crate::CanId), so it has no default.
+    ///
+    /// This applies to every method that writes a [`CanFdFrame`], e.g. [`FdCanUSB::write`] and [`FdCanUSB::transfer_many`],
+    /// but not to [`FdCanUSB::write_raw`].
+    pub fn set_default_flags(
+        &mut self,
+        brs: Option<bool>,
+        fd_can_frame: Option<bool>,
+        remote_frame: Option<bool>,
+    ) {
+        self.default_flags = (brs, fd_can_frame, remote_frame);
+    }
+
+    /// Set the line ending of written commands, `\n` by default.
+    ///
+    /// The FdCanUSB accepts either, but some serial-over-network adapters and terminals
+    /// only forward a line once they see `\r\n`, so commands seem delayed until the next one is written.
+    /// This applies to every write, including [`FdCanUSB::write_raw`] if the frame ends with a newline.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    /// Convert the device timestamp of a received frame into a host time.
+    ///
+    /// The first timestamp converted is paired with the epoch set by [`FdCanUSB::set_timestamp_epoch`],
+    /// or with the current time, and later timestamps are relative to it.
+    /// The device's `u32` microsecond counter wraps about every 71 minutes, which is detected when a timestamp is
+    /// lower than the last one, so this must be called for received frames in order,
+    /// and at least once per wrap, to give a monotonic time.
+    ///
+    /// Returns `None` if the frame has no timestamp.
+    pub fn host_timestamp(&mut self, frame: &CanFdFrame) -> Option<std::time::SystemTime> {
+        Some(self.clock.host_time(frame.timestamp?))
+    }
+
+    /// Set the host time to pair with the next timestamp converted by [`FdCanUSB::host_timestamp`],
+    /// e.g. the start of a capture.
+    pub fn set_timestamp_epoch(&mut self, epoch: std::time::SystemTime) {
+        self.clock.set_epoch(epoch);
+    }
+
+    /// Set the maximum length of a packet read from the [FdCanUSB], which defaults to the length of the buffer.
+    ///
+    /// If this many bytes are buffered without a newline, the read fails with [`ReadError::LineTooLong`]
+    /// and the bytes are discarded, bounding the work done for a device sending garbage.
+    /// The length is also limited by the length of the buffer.
+    pub fn set_max_line_len(&mut self, max_line_len: usize) {
+        self.max_line_len = max_line_len;
+    }
+
+    /// Install a function to be called with the exact bytes written to and read from the transport.
+    ///
+    /// Bytes are passed as they are written or read, so a single packet may be split over multiple calls,
+    /// or multiple packets combined into one. This is useful for debugging the protocol, e.g. lost sync issues.
+    /// There is no overhead when no logger is installed.
+    pub fn set_raw_logger(&mut self, logger: impl FnMut(Direction, &[u8]) + Send + 'static) {
+        self.raw_logger = Some(RawLogger(Box::new(logger)));
+    }
+
+    /// Set whether each frame written and read is logged, which is enabled by default.
+    ///
+    /// Frames are logged at the `debug` level, which floods the log on a busy bus.
+    /// Disabling this silences the frames of this [FdCanUSB] only, without changing the global log level,
+    /// so frame logging can be enabled for a single device, or for a window of time.
+    /// When disabled, nothing is formatted, so it costs a single check per frame.
+    /// Other events, such as retries and resyncs, are still logged.
+    pub fn set_log_frames(&mut self, log_frames: bool) {
+        self.log_frames = log_frames;
+    }
+
+    /// Whether each frame written and read is logged, see [`FdCanUSB::set_log_frames`].
+    pub fn log_frames(&self) -> bool {
+        self.log_frames
+    }
+
+    /// Install a function to be called when a frame is received with a timestamp earlier than the frame before it.
+    ///
+    /// The function is called with the previous timestamp and the frame, which is still returned as normal.
+    /// The FdCanUSB's timestamp counter only goes back if the device reset, or frames were reordered,
+    /// so this can be used to detect resets during a long run. Frames are checked in the order they are read from the device,
+    /// and frames without a timestamp are not checked.
+    ///
+    /// The counter wraps about every 71 minutes, so a step forward of more than half of that, about 36 minutes,
+    /// is taken as a step back. Nothing is checked unless a function is installed.
+    pub fn set_timestamp_regression_handler(
+        &mut self,
+        handler: impl FnMut(u32, &CanFdFrame) + Send + 'static,
+    ) {
+        self.timestamp_check = Some(TimestampCheck::new(Box::new(handler)));
+    }
+
+    /// Check the timestamp of a received frame, see [`FdCanUSB::set_timestamp_regression_handler`].
+    fn check_timestamp(&mut self, frame: &CanFdFrame) {
+        if let Some(check) = &mut self.timestamp_check {
+            check.check(frame);
+        }
+    }
+
+    /// Set the source of the current time for timeouts, [`SystemClock`](crate::SystemClock) by default.
+    ///
+    /// This is intended for tests, where a clock which is advanced manually makes timeouts deterministic.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.time = Box::new(clock);
+    }
+
+    /// The current time, from the [`Clock`].
+    fn now(&self) -> std::time::Instant {
+        self.time.now()
+    }
+
+    /// The frame and error counters for this [FdCanUSB].
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Reset all of the [`Stats`] counters to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    /// Read every frame that is already buffered, without waiting for more data from the transport.
+    ///
+    /// Frames are returned in the order they were received.
+    /// A partial packet at the end of the buffer is kept for the next read.
+    ///
+    /// Like the other reads, empty lines and echoes of the last write are skipped.
+    ///
+    /// If a packet can not be parsed, the error is returned and any frames parsed before it are kept for the next read.
+    pub fn read_available(&mut self) -> Result<Vec<CanFdFrame>, ReadError> {
+        while let Some(packet) = self.buffered_newline() {
+            let packet = &self.buffer.as_ref()[self.used_bytes..packet];
+            self.used_bytes += packet.len();
+            let packet = trim_line_end(packet);
+            if packet.is_empty() {
+                trace!("skipped empty line");
+                continue;
+            }
+            if is_echo(&self.last_written, packet) {
+                debug!("skipped echo {:?}", String::from_utf8_lossy(packet));
+                continue;
+            }
+            if self.pending_acks > 0 && (packet.starts_with(b"OK") || packet.starts_with(b"ERR")) {
+                self.pending_acks -= 1;
+                if packet.starts_with(b"OK") {
+                    continue;
+                }
+            }
+            let response = if packet.starts_with(b"rcv") {
+                parse_rcv(packet, self.log_frames)
+            } else {
+                Err(unexpected_packet("rcv", packet))
+            };
+            let response = response.inspect_err(|e| self.stats.record_error(e))?;
+            self.check_timestamp(&response);
+            self.pending.push_back(response);
+        }
+        self.stats.frames_received += self.pending.len() as u64;
+        Ok(self.pending.drain(..).collect())
+    }
+
+    /// Transfer a frame, and wait for the reply from the device it was sent to.
+    ///
+    /// The reply is found using the moteus arbitration id convention, where a frame's id is `source << 8 | destination`,
+    /// with the `0x8000` bit set if a reply is requested.
+    /// The reply to a frame sent from `source` to `destination` therefore has the id `destination << 8 | source`.
+    ///
+    /// Frames from other devices are queued for later reads, rather than discarded.
+    /// Use [`FdCanUSB::query_matching`] to find the reply with a different convention.
+    pub fn query(&mut self, frame: CanFdFrame) -> Result<CanFdFrame, TransferError> {
+        let source = u32::from(frame.source_id());
+        let destination = frame.arbitration_id.as_raw() & 0xFF;
+        let reply_id = (destination << 8) | source;
+        self.query_matching(frame, |response| {
+            response.arbitration_id.as_raw() == reply_id
+        })
+    }
+
+    /// Transfer a frame, and wait for the first response for which `matches` returns `true`.
+    ///
+    /// Frames that do not match are queued for later reads, rather than discarded.
+    pub fn query_matching(
+        &mut self,
+        frame: CanFdFrame,
+        matches: impl FnMut(&CanFdFrame) -> bool,
+    ) -> Result<CanFdFrame, TransferError> {
+        self.write(frame)?;
+        let response = self
+            .read_matching(matches, DEFAULT_TIMEOUT)
+            .inspect_err(|e| self.stats.record_error(e))?;
+        self.stats.frames_received += 1;
+        Ok(response)
+    }
+
+    /// Read a frame from the device with the given source id, see [`CanFdFrame::is_from`].
+    ///
+    /// On a busy bus, [`FdCanUSB::read`] may return a frame unrelated to the last command.
+    /// Frames from other devices are queued for later reads, rather than discarded.
+    pub fn read_from(&mut self, expected_id: u16) -> Result<CanFdFrame, ReadError> {
+        let response = self
+            .read_matching(|frame| frame.is_from(expected_id), DEFAULT_TIMEOUT)
+            .inspect_err(|e| self.stats.record_error(e))?;
+        self.stats.frames_received += 1;
+        Ok(response)
+    }
+
+    /// Reads `rcv` packets until one `matches`, queueing the others.
+    /// Queued frames are checked first.
+    fn read_matching(
+        &mut self,
+        mut matches: impl FnMut(&CanFdFrame) -> bool,
+        timeout: Duration,
+    ) -> Result<CanFdFrame, ReadError> {
+        if let Some(index) = self.pending.iter().position(&mut matches) {
+            return Ok(self.pending.remove(index).expect("index is in bounds"));
+        }
+        let deadline = self.now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(self.now());
+            let response = self.read_rcv_packet(timeout)?;
+            if matches(&response) {
+                return Ok(response);
+            }
+            self.pending.push_back(response);
+        }
+    }
+
+    /// Returns the first queued frame, or reads a single `rcv` packet
+    fn read_rcv(&mut self, timeout: Duration) -> Result<CanFdFrame, ReadError> {
+        match self.pending.pop_front() {
+            Some(response) => Ok(response),
+            None => self.read_rcv_packet(timeout),
+        }
+    }
+
+    /// Reads a single `rcv` packet
+    ///
+    /// The `OK` or `ERR` replies for frames written without waiting for them are read first, see [`FdCanUSB::pending_acks`].
+    fn read_rcv_packet(&mut self, timeout: Duration) -> Result<CanFdFrame, ReadError> {
+        let deadline = self.now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(self.now());
+            let pending_acks = self.pending_acks;
+            let log_frames = self.log_frames;
+            let packet = self.read_packet(timeout)?;
+            if packet.starts_with(b"rcv") {
+                let response = parse_rcv(packet, log_frames)?;
+                self.check_timestamp(&response);
+                return Ok(response);
+            }
+            let error = unexpected_packet("rcv", packet);
+            match error {
+                ReadError::DeviceError { .. } if pending_acks > 0 => {
+                    self.pending_acks -= 1;
+                    return Err(error);
+                }
+                ReadError::LostSync { .. } if pending_acks > 0 && packet.starts_with(b"OK") => {
+                    self.pending_acks -= 1;
+                }
+                error => return Err(error),
+            }
+        }
+    }
+
+    /// Write a frame, and wait for its `OK`.
+    fn write_acked(&mut self, frame: &FdCanUSBFrame) -> Result<Ack, TransferError> {
+        self.write_frame(frame)?;
+        self.compact();
+        let ack = self
+            .read_ok()
+            .map_err(|e| with_command(e, frame.as_bytes()))?;
+        Ok(ack)
+    }
+
+    /// Encode a frame into `scratch`, write it, and wait for its `OK`.
+    fn write_scratch(
+        &mut self,
+        frame: &CanFdFrame,
+        scratch: &mut [u8],
+    ) -> Result<(), TransferError> {
+        let len = match frame.encode_into_with_defaults(self.default_flags, scratch) {
+            Ok(len) => len,
+            Err(EncodeError::InvalidFrameLength(e)) => return Err(e.into()),
+            Err(EncodeError::InvalidDlc(e)) => return Err(FrameValidationError::from(e).into()),
+            Err(EncodeError::InvalidId(e)) => return Err(FrameValidationError::from(e).into()),
+            Err(EncodeError::BufferTooSmall { .. }) => {
+                unreachable!("every frame fits in `MAX_ENCODED_LEN` bytes")
+            }
+        };
+        let line = &scratch[..len];
+        self.write_line(line)?;
+        self.compact();
+        self.read_ok().map_err(|e| with_command(e, line))?;
+        Ok(())
+    }
+
+    /// Encode a frame to be written, checking it is within the maximum data length.
+    /// Any flags the frame leaves as `None` are set to the defaults, see [`FdCanUSB::set_default_flags`].
+    /// The frame's flags must also be consistent, see [`CanFdFrame::validate`].
+    fn encode(&self, mut frame: CanFdFrame) -> Result<FdCanUSBFrame, TransferError> {
+        let (brs, fd_can_frame, remote_frame) = self.default_flags;
+        frame.brs = frame.brs.or(brs);
+        frame.fd_can_frame = frame.fd_can_frame.or(fd_can_frame);
+        frame.remote_frame = frame.remote_frame.or(remote_frame);
+        self.check_data_len(&frame, frame.fd_can_frame)?;
+        frame.validate()?;
+        Ok(frame.into())
+    }
+
+    /// Check a frame to be written, with the given `fd_can_frame` flag, is within the maximum data length.
+    fn check_data_len(
+        &self,
+        frame: &CanFdFrame,
+        fd_can_frame: Option<bool>,
+    ) -> Result<(), InvalidFrameLength> {
+        let max = match fd_can_frame {
+            Some(false) => self.max_data_len.min(8),
+            _ => self.max_data_len,
+        };
+        // The data is padded to a DLC override when written
+        if frame.dlc() > max {
+            return Err(InvalidFrameLength {
+                len: frame.dlc(),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write a frame to the FdCanUSB
+    ///
+    /// Frames are logged at the `debug` level by default, unless disabled with [`FdCanUSB::set_log_frames`].
+    /// Each line is logged once it has been written, see [`log_tx`] for the `tracing` events.
+    fn write_frame(&mut self, frame: &FdCanUSBFrame) -> Result<(), WriteError> {
+        self.write_line(frame.as_bytes())
+    }
+
+    /// Write an encoded line to the FdCanUSB, see [`FdCanUSB::write_frame`].
+    ///
+    /// The line is written from `last_written`, with its newline replaced by the [`LineEnding`].
+    fn write_line(&mut self, line: &[u8]) -> Result<(), WriteError> {
+        self.write_unsent()?;
+        self.wait_interframe_gap();
+        self.last_written.clear();
+        self.line_ending.push_line(&mut self.last_written, line);
+        let line = &self.last_written;
+        self.last_write_at = Some(self.now());
+        self.transport.write_all(line)?;
+        if self.log_frames {
+            log_tx(line);
+        }
+        if let Some(logger) = &mut self.raw_logger {
+            (logger.0)(Direction::Tx, line);
+        }
+        self.stats.frames_sent += 1;
+        Ok(())
+    }
+
+    /// Flush the transport, then discard any queued frames and read packets until none arrives for [`DRAIN_QUIET_TIME`].
+    ///
+    /// Each `OK` or `ERR` read counts towards [`FdCanUSB::pending_acks`], so the acks still in flight are kept.
+    fn discard_buffered(&mut self) -> Result<(), ReadError> {
+        self.transport.flush()?;
+        self.pending.clear();
+        loop {
+            match self.read_newline(DRAIN_QUIET_TIME) {
+                Ok(end) => {
+                    let packet = trim_line_end(&self.buffer.as_ref()[self.used_bytes..end]);
+                    let is_ack = packet.starts_with(b"OK") || packet.starts_with(b"ERR");
+                    trace!("discarded {:?}", String::from_utf8_lossy(packet));
+                    self.used_bytes = end;
+                    if is_ack {
+                        self.pending_acks = self.pending_acks.saturating_sub(1);
+                    }
+                }
+                // The long line has already been skipped
+                Err(ReadError::LineTooLong { .. }) => {}
+                Err(e) if e.is_timeout() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        self.read_len = 0;
+        self.used_bytes = 0;
+        Ok(())
+    }
+
+    /// Reads bytes into the buffer and returns the end pos of one packet.
+    /// Packets end with `\r\n` (or a bare `\n`), which is included in the packet and removed by [`trim_line_end`].
+    /// Returns a `Timeout` error if no packet is read within `timeout`, or `LineTooLong` if the packet is too long.
+    ///
+    /// If the transport's read timeout can be set, each read waits at most until the deadline,
+    /// so a packet arriving in pieces just before the deadline is not dropped by a read that blocks past it.
+    fn read_newline(&mut self, timeout: Duration) -> Result<usize, ReadError> {
+        let deadline = self.now() + timeout;
+        loop {
+            // A bounded line length stops a stuck device from filling the buffer without a newline
+            let max = self.max_line_len.min(self.buffer.as_ref().len());
+            if let Some(end) = self.buffered_newline() {
+                if end - self.used_bytes > max {
+                    self.used_bytes = end;
+                    return Err(ReadError::LineTooLong { max });
+                }
+                return Ok(end);
+            }
+            if self.read_len - self.used_bytes >= max {
+                self.used_bytes = self.read_len;
+                return Err(ReadError::LineTooLong { max });
+            }
+            if self.read_len == self.buffer.as_ref().len() {
+                self.compact();
+            }
+            let now = self.now();
+            if now > deadline {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out waiting for newline",
+                )
+                .into());
+            }
+            if let Some(set_read_timeout) = self.set_read_timeout {
+                // A zero timeout may mean "never time out", so wait at least a millisecond
+                let remaining = (deadline - now).max(Duration::from_millis(1));
+                set_read_timeout(&mut self.transport, remaining)?;
+            }
+            let buffer = self.buffer.as_mut();
+            let read_num = self.transport.read(&mut buffer[self.read_len..])?;
+            trace!(
+                "read {} {:?}",
+                read_num,
+                &buffer[self.read_len..self.read_len + read_num]
+            );
+            if let Some(logger) = &mut self.raw_logger {
+                (logger.0)(
+                    Direction::Rx,
+                    &buffer[self.read_len..self.read_len + read_num],
+                );
+            }
+            if self.recent_len > 0 {
+                self.recent
+                    .extend_from_slice(&buffer[self.read_len..self.read_len + read_num]);
+                // Trimming only once twice the length is kept makes each byte cheap to keep
+                if self.recent.len() > 2 * self.recent_len {
+                    self.recent.drain(..self.recent.len() - self.recent_len);
+                }
+            }
+            self.read_len += read_num;
+        }
+    }
+
+    /// Write a console `command`, and read the lines of the reply until the `OK`.
+    ///
+    /// Lines are trimmed, and any `rcv` frames received are queued for later reads.
+    fn command_lines(&mut self, command: &str) -> Result<Vec<String>, TransferError> {
+        let command = FdCanUSBFrame::from(format!("{command}\n").as_str());
+        self.write_frame(&command)?;
+        self.compact();
+        let mut lines = Vec::new();
+        self.read_until_ok(|packet| {
+            lines.push(String::from_utf8_lossy(packet).trim().to_string());
+            Ok(())
+        })
+        .inspect_err(|e| self.stats.record_error(e))
+        .map_err(|e| with_command(e, command.as_bytes()))?;
+        Ok(lines)
+    }
+
+    /// Reads a single packet, with the line terminator removed.
+    ///
+    /// Empty lines, which the FdCanUSB may send between packets, are skipped within the same `timeout`.
+    fn read_packet(&mut self, timeout: Duration) -> Result<&[u8], ReadError> {
+        let (start, end) = self.read_packet_range(timeout)?;
+        Ok(&self.buffer.as_ref()[start..end])
+    }
+
+    /// Reads a single packet like [`FdCanUSB::read_packet`], returning its range in the buffer,
+    /// so the buffer is not borrowed while handling an error.
+    fn read_packet_range(&mut self, timeout: Duration) -> Result<(usize, usize), ReadError> {
+        let deadline = self.now() + timeout;
+        let (start, end) = loop {
+            let timeout = deadline.saturating_duration_since(self.now());
+            let end = self.read_newline(timeout)?;
+            let start = self.used_bytes;
+            self.used_bytes = end;
+            if !trim_line_end(&self.buffer.as_ref()[start..end]).is_empty() {
+                break (start, end);
+            }
+            trace!("skipped empty line");
+        };
+        let len = trim_line_end(&self.buffer.as_ref()[start..end]).len();
+        Ok((start, start + len))
+    }
+
+    /// Returns the end pos of a packet already in the buffer, without reading from the transport.
+    fn buffered_newline(&self) -> Option<usize> {
+        let buffer = self.buffer.as_ref();
+        let pos = buffer[self.used_bytes..self.read_len]
+            .iter()
+            .position(|&c| c == b'\n')?;
+        trace!(
+            "raw packet {:?}",
+            &buffer[self.used_bytes..self.used_bytes + pos + 1]
+        );
+        Some(self.used_bytes + pos + 1)
+    }
+
+    /// The [FdCanUSB] responds with `OK` after a correct frame is parsed.
+    /// `read_ok` waits for this response, and returns an error if it is not received.
+    ///
+    /// Any `rcv` frames read before the `OK` are queued, to be returned by the next calls to [`FdCanUSB::read`].
+    fn read_ok(&mut self) -> Result<Ack, ReadError> {
+        self.read_ack().inspect_err(|e| self.stats.record_error(e))
+    }
+
+    /// Reads a single `OK` packet
+    fn read_ack(&mut self) -> Result<Ack, ReadError> {
+        self.read_until_ok(|packet| Err(unexpected_packet("OK", packet)))
+    }
+
+    /// Reads packets until an `OK`, passing any that are not `rcv` frames or `ERR` replies to `on_packet`.
+    /// `rcv` frames are queued, to be returned by the next reads, and the `OK` is returned as an [`Ack`].
+    ///
+    /// A line matching one just written is skipped, as it is an echo of the command,
+    /// e.g. from a TCP bridge or firmware with echo enabled.
+    ///
+    /// The `OK`s of frames written without waiting for them are read first, see [`FdCanUSB::pending_acks`].
+    fn read_until_ok(
+        &mut self,
+        mut on_packet: impl FnMut(&[u8]) -> Result<(), ReadError>,
+    ) -> Result<Ack, ReadError> {
+        while self.pending_acks > 0 {
+            match self.read_next_ok(&mut on_packet) {
+                Ok(_) => {}
+                // The error is for an earlier frame, not the one waiting for this `OK`
+                Err(e @ ReadError::DeviceError { .. }) => {
+                    debug!(
+                        "frame written without waiting for its OK was rejected: {}",
+                        e
+                    );
+                    self.stats.record_error(&e);
+                }
+                Err(e) => return Err(e),
+            }
+            self.pending_acks -= 1;
+        }
+        self.read_next_ok(on_packet)
+    }
+
+    /// Reads packets until the next `OK`, see [`FdCanUSB::read_until_ok`].
+    fn read_next_ok(
+        &mut self,
+        mut on_packet: impl FnMut(&[u8]) -> Result<(), ReadError>,
+    ) -> Result<Ack, ReadError> {
+        loop {
+            // Not `read_packet`, so the packet only borrows the buffer, and can be compared with `last_written`
+            let end = self.read_newline(DEFAULT_TIMEOUT)?;
+            let packet = &self.buffer.as_ref()[self.used_bytes..end];
+            self.used_bytes = end;
+            let packet = trim_line_end(packet);
+            if packet.is_empty() {
+                trace!("skipped empty line");
+            } else if is_echo(&self.last_written, packet) {
+                debug!("skipped echo {:?}", String::from_utf8_lossy(packet));
+            } else if let Some(fields) = packet.strip_prefix(b"OK") {
+                return Ok(Ack::from_fields(fields));
+            } else if packet.starts_with(b"rcv") {
+                let response = parse_rcv(packet, self.log_frames)?;
+                self.check_timestamp(&response);
+                self.pending.push_back(response);
+            } else if packet.starts_with(b"ERR") {
+                return Err(unexpected_packet("OK", packet));
+            } else {
+                on_packet(packet)?;
+            }
+        }
+    }
+}
+
+/// An iterator over received frames, created with [`FdCanUSB::incoming`].
+#[derive(Debug)]
+pub struct Incoming<'a, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fdcanusb: &'a mut FdCanUSB<T, Buffer>,
+    blocking: bool,
+}
+
+impl<T, Buffer> Incoming<'_, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Keep waiting for frames forever, rather than ending the iterator on a timeout.
+    pub fn blocking(mut self) -> Self {
+        self.blocking = true;
+        self
+    }
+}
+
+impl<T, Buffer> Iterator for Incoming<'_, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    type Item = Result<CanFdFrame, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdcanusb.read() {
+                Err(ReadError::Timeout(_)) if self.blocking => continue,
+                Err(ReadError::Timeout(_)) => return None,
+                result => return Some(result),
+            }
+        }
+    }
+}
+
+/// The `OK` acknowledgment of a written frame, see [`FdCanUSB::write_returning_ack`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Ack {
+    /// The whitespace separated fields after the `OK`, which is empty for a bare `OK`.
+    pub fields: Vec<String>,
+}
+
+impl Ack {
+    fn from_fields(fields: &[u8]) -> Ack {
+        Ack {
+            fields: fields
+                .split(u8::is_ascii_whitespace)
+                .filter(|field| !field.is_empty())
+                .map(|field| String::from_utf8_lossy(field).into_owned())
+                .collect(),
+        }
+    }
+}
+
+/// Whether [`FdCanUSB::transfer_single`] waits for a response frame.
+///
+/// A `bool` can be used instead of [`ExpectResponse::Yes`] and [`ExpectResponse::No`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectResponse {
+    /// Wait for a response frame.
+    Yes,
+    /// Do not wait for a response frame.
+    No,
+    /// Wait for a response frame if the frame requests one, see [`CanFdFrame::expects_response`].
+    FromId,
+}
+
+impl From<bool> for ExpectResponse {
+    fn from(response: bool) -> Self {
+        if response {
+            ExpectResponse::Yes
+        } else {
+            ExpectResponse::No
+        }
+    }
+}
+
+/// The result of [`FdCanUSB::try_write`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStatus {
+    /// The frame was taken, though part of it may be left to write, see [`FdCanUSB::try_write`].
+    Sent,
+    /// The transport is full, so nothing was written.
+    WouldBlock,
+}
+
+/// Returns `true` if a write failed because the transport is full, rather than failing for good.
+fn is_would_block(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// The line ending of commands written to the [FdCanUSB], see [`FdCanUSB::set_line_ending`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// A bare `\n`.
+    #[default]
+    Lf,
+    /// `\r\n`.
+    CrLf,
+}
+
+impl LineEnding {
+    /// The bytes of the line ending.
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+
+    /// Append `line` to `out`, replacing its newline, if it has one, with this line ending.
+    fn push_line(self, out: &mut Vec<u8>, line: &[u8]) {
+        match line.strip_suffix(b"\n") {
+            Some(_) => {
+                out.extend_from_slice(trim_line_end(line));
+                out.extend_from_slice(self.as_bytes());
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+}
+
+/// The direction of bytes passed to a raw logger, see [`FdCanUSB::set_raw_logger`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes written to the [FdCanUSB].
+    Tx,
+    /// Bytes read from the [FdCanUSB].
+    Rx,
+}
+
+/// A function called with raw bytes and their [`Direction`].
+type RawLoggerFn = dyn FnMut(Direction, &[u8]) + Send;
+
+/// A raw logger installed with [`FdCanUSB::set_raw_logger`].
+struct RawLogger(Box<RawLoggerFn>);
+
+impl std::fmt::Debug for RawLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RawLogger")
+    }
+}
+
+/// Remove the `\n` or `\r\n` line terminator from the end of a packet.
+fn trim_line_end(packet: &[u8]) -> &[u8] {
+    let packet = packet.strip_suffix(b"\n").unwrap_or(packet);
+    packet.strip_suffix(b"\r").unwrap_or(packet)
+}
+
+/// Returns `true` if `packet` is the banner the [FdCanUSB] sends when it resets.
+fn is_banner(packet: &[u8]) -> bool {
+    packet
+        .get(..BANNER_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(BANNER_PREFIX))
+}
+
+/// Returns `true` if `packet` is one of the lines of `written`, so it is an echo of a command.
+fn is_echo(written: &[u8], packet: &[u8]) -> bool {
+    !packet.is_empty()
+        && written
+            .split_inclusive(|&c| c == b'\n')
+            .any(|line| trim_line_end(line) == packet)
+}
+
+/// The error for a packet that did not start with `expected`.
+///
+/// Packets starting with `ERR` are reported by the [FdCanUSB] when it rejects a command,
+/// anything else means the stream is out of sync.
+fn unexpected_packet(expected: &str, packet: &[u8]) -> ReadError {
+    match packet.strip_prefix(b"ERR") {
+        Some(message) => ReadError::DeviceError {
+            message: String::from_utf8_lossy(message).trim().to_string(),
+            command: None,
+        },
+        None => ReadError::LostSync {
+            expected: expected.to_string(),
+            received: String::from_utf8_lossy(packet).to_string(),
+        },
+    }
+}
+
+/// Add the rejected `command` to a [`ReadError::DeviceError`].
+fn with_command(error: ReadError, command: &[u8]) -> ReadError {
+    match error {
+        ReadError::DeviceError { message, .. } => ReadError::DeviceError {
+            message,
+            command: Some(String::from_utf8_lossy(command).trim().to_string()),
+        },
+        e => e,
+    }
+}
+
+/// Log a line once it has been written to the [FdCanUSB], see [`FdCanUSB::set_log_frames`].
+///
+/// With the `tracing` feature, the line is a `trace` event, and a `can send` command is also a `debug` event
+/// with the structured fields of the frame.
+fn log_tx(line: &[u8]) {
+    #[cfg(not(feature = "tracing"))]
+    debug!("> {:?}", String::from_utf8_lossy(line));
+    #[cfg(feature = "tracing")]
+    {
+        tracing::trace!(direction = "tx", line = %String::from_utf8_lossy(line));
+        // A `can send` command has the same fields as a `rcv` line, which is only parsed if the event is enabled
+        let fields = line
+            .strip_prefix(b"can send")
+            .filter(|_| tracing::enabled!(tracing::Level::DEBUG));
+        if let Some(fields) = fields {
+            if let Ok(frame) = CanFdFrame::from_bytes(&[b"rcv".as_slice(), fields].concat()) {
+                tracing::debug!(
+                    direction = "tx",
+                    arbitration_id = frame.arbitration_id.as_raw(),
+                    data_len = frame.data.len(),
+                    "{:?}",
+                    frame
+                );
+            }
+        }
+    }
+}
+
+/// Parse a `rcv` packet read from the [FdCanUSB].
+///
+/// With the `tracing` feature, the line is a `trace` event, and the frame is a `debug` event with structured fields.
+/// Nothing is logged, or formatted, unless `log_frames` is set, see [`FdCanUSB::set_log_frames`].
+fn parse_rcv(packet: &[u8], log_frames: bool) -> Result<CanFdFrame, ReadError> {
+    if log_frames {
+        #[cfg(not(feature = "tracing"))]
+        debug!("< {:?}", String::from_utf8_lossy(packet));
+        #[cfg(feature = "tracing")]
+        tracing::trace!(direction = "rx", line = %String::from_utf8_lossy(packet));
+    }
+    let frame = CanFdFrame::from_bytes(packet)?;
+    #[cfg(feature = "tracing")]
+    if log_frames {
+        tracing::debug!(
+            direction = "rx",
+            arbitration_id = frame.arbitration_id.as_raw(),
+            data_len = frame.data.len(),
+            "{:?}",
+            frame
+        );
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fdcanusb() {
+        let mut fdcanusb = FdCanUSB::open("/dev/fdcanusb", serial2::KeepSettings)
+            .expect("Failed to open fdcanusb");
+        let frame = FdCanUSBFrame::from(
+            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050 b\n",
+        );
+        // let frame= FdCanUSBFrame::from("can send 8001 01000011001F01130D505050 b");
+
+        fdcanusb.write_raw(frame).expect("Failed to write frame");
+        let respsonse = fdcanusb.read();
+        dbg!(&respsonse);
+        assert!(respsonse.is_ok());
+    }
+
+    /// A transport that replays `rx` and records everything written to `tx`.
+    /// Reads past the end of `rx` time out, as a [`serial2::SerialPort`] would.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockTransport {
+        rx: std::io::Cursor<Vec<u8>>,
+        pub(crate) tx: Vec<u8>,
+        pub(crate) read_timeouts: Vec<Duration>,
+    }
+
+    impl MockTransport {
+        pub(crate) fn new(rx: &[u8]) -> Self {
+            Self {
+                rx: std::io::Cursor::new(rx.to_vec()),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl std::io::Read for MockTransport {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.rx.read(buf)? {
+                0 => Err(std::io::ErrorKind::TimedOut.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl std::io::Write for MockTransport {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.tx.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transfer_many() {
+        let transport = MockTransport::new(b"OK\r\nrcv 0100 01\r\nOK\r\nrcv 0200 02\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frames = [
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+            CanFdFrame::new(0x8002, &[2]).unwrap(),
+        ];
+        let responses = fdcanusb
+            .transfer_many(frames, 1)
+            .expect("Failed to transfer frames");
+        assert_eq!(
+            fdcanusb.transport.tx,
+            b"can send 8001 01 E\ncan send 8002 02 E\n".to_vec()
+        );
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].arbitration_id.as_raw(), 0x0100);
+        assert_eq!(responses[1].data, vec![2]);
+    }
+
+    #[test]
+    fn test_transfer_many_reports_failed_index() {
+        let transport = MockTransport::new(b"OK\r\nERR bad command\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frames = [
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+            CanFdFrame::new(0x8002, &[2]).unwrap(),
+        ];
+        let error = fdcanusb.transfer_many(frames, 0).unwrap_err();
+        assert!(matches!(error, TransferError::Frame { index: 1, .. }));
+
+        // The `OK` of the frame written after the rejected one is still to come
+        let transport = MockTransport::new(b"ERR bad command\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frames = [
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+            CanFdFrame::new(0x8002, &[2]).unwrap(),
+        ];
+        let error = fdcanusb.transfer_many(frames, 0).unwrap_err();
+        assert!(matches!(error, TransferError::Frame { index: 0, .. }));
+        assert_eq!(fdcanusb.pending_acks(), 1);
+
+        // A missing response is not blamed on a frame
+        let transport = MockTransport::new(b"OK\r\nOK\r\nrcv 0100 01\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frames = [
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+            CanFdFrame::new(0x8002, &[2]).unwrap(),
+        ];
+        let error = fdcanusb.transfer_many(frames, 1).unwrap_err();
+        assert!(matches!(error, TransferError::Read(ReadError::Timeout(_))));
+        assert_eq!(fdcanusb.pending_acks(), 0);
+    }
+
+    #[test]
+    fn test_query() {
+        let transport = MockTransport::new(b"OK\r\nrcv 0200 02\r\nrcv 0100 01\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let response = fdcanusb
+            .query(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .expect("Failed to query");
+        assert_eq!(response.arbitration_id.as_raw(), 0x0100);
+        let queued = fdcanusb.read().expect("Failed to read queued frame");
+        assert_eq!(queued.arbitration_id.as_raw(), 0x0200);
+    }
+
+    #[test]
+    fn test_read_available() {
+        let transport = MockTransport::new(b"rcv 0100 01\r\nrcv 0100 02\r\nrcv 0100 03\r\nrcv 01");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![1]);
+        let responses = fdcanusb
+            .read_available()
+            .expect("Failed to read available frames");
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].data, vec![3]);
+        assert!(fdcanusb.read_available().unwrap().is_empty());
+        assert_eq!(
+            &fdcanusb.buffer[fdcanusb.used_bytes..fdcanusb.read_len],
+            b"rcv 01"
+        );
+
+        // A keepalive empty line and an echo are skipped, as by `read`
+        let transport =
+            MockTransport::new(b"OK\r\nrcv 0100 01\r\ncan send 8001 01 E\r\n\r\nrcv 8001 00\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+        let responses = fdcanusb.read_available().unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].data, [0]);
+    }
+
+    #[test]
+    fn test_write_keeps_buffered_frames() {
+        let transport = MockTransport::new(b"rcv 0100 01\r\nrcv 0100 02\r\nOK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![1]);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .expect("Failed to write frame");
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![2]);
+    }
+
+    #[test]
+    fn test_conf_enumerate() {
+        let transport = MockTransport::new(
+            b"can.bitrate 1000000\r\nrcv 0100 01\r\ncan.fd_bitrate 5000000\r\nOK\r\n",
+        );
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let pairs = fdcanusb.conf_enumerate().expect("Failed to enumerate");
+        assert_eq!(fdcanusb.transport.tx, b"conf enumerate\n".to_vec());
+        assert_eq!(
+            pairs,
+            vec![
+                ("can.bitrate".to_string(), "1000000".to_string()),
+                ("can.fd_bitrate".to_string(), "5000000".to_string()),
+            ]
+        );
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![1]);
+    }
+
+    #[test]
+    fn test_raw_logger() {
+        let transport = MockTransport::new(b"OK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let logger_log = log.clone();
+        fdcanusb.set_raw_logger(move |direction, bytes| {
+            logger_log.lock().unwrap().push((direction, bytes.to_vec()))
+        });
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .expect("Failed to write frame");
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (Direction::Tx, b"can send 8001 01 E\n".to_vec()),
+                (Direction::Rx, b"OK\r\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_terminators() {
+        let transport = MockTransport::new(b"rcv 8001 00\r\nrcv 8001 01\ngarbage\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        assert_eq!(
+            fdcanusb.read().expect("Failed to read frame").data,
+            vec![0x00]
+        );
+        assert_eq!(
+            fdcanusb.read().expect("Failed to read frame").data,
+            vec![0x01]
+        );
+        match fdcanusb.read() {
+            Err(ReadError::LostSync { received, .. }) => assert_eq!(received, "garbage"),
+            e => panic!("Expected lost sync, got {e:?}"),
+        }
+        assert_eq!(trim_line_end(b"rcv 8001 00\r\n"), b"rcv 8001 00");
+        assert_eq!(trim_line_end(b"rcv 8001 00\n"), b"rcv 8001 00");
+    }
+
+    #[test]
+    fn test_device_error() {
+        let transport = MockTransport::new(b"ERR unknown command\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let error = fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap_err();
+        match error {
+            TransferError::Read(ReadError::DeviceError { message, command }) => {
+                assert_eq!(message, "unknown command");
+                assert_eq!(command.as_deref(), Some("can send 8001 01 E"));
+            }
+            e => panic!("Expected a device error, got {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let transport = MockTransport::new(b"OK\r\nrcv 0100 01\r\ngarbage\r\nrcv 01 0\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .expect("Failed to write frame");
+        fdcanusb.read().expect("Failed to read frame");
+        assert!(matches!(fdcanusb.read(), Err(ReadError::LostSync { .. })));
+        assert!(matches!(fdcanusb.read(), Err(ReadError::Parse(_))));
+        assert!(matches!(fdcanusb.read(), Err(ReadError::Timeout(_))));
+        assert_eq!(
+            fdcanusb.stats(),
+            &Stats {
+                frames_sent: 1,
+                frames_received: 1,
+                lost_sync: 1,
+                device_errors: 0,
+                timeouts: 1,
+                parse_errors: 1,
+            }
+        );
+        fdcanusb.reset_stats();
+        assert_eq!(fdcanusb.stats(), &Stats::default());
+    }
+
+    #[test]
+    fn test_transfer_with_retries() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"ERR bad\r\nOK\r\n"));
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        let error = fdcanusb
+            .transfer_with_retries(frame.clone(), false, 3)
+            .unwrap_err();
+        assert!(!error.is_retryable());
+
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\n"));
+        let error = fdcanusb
+            .transfer_with_retries(frame.clone(), true, 2)
+            .unwrap_err();
+        assert!(matches!(error, TransferError::Read(ReadError::Timeout(_))));
+        assert_eq!(fdcanusb.stats().frames_sent, 3);
+
+        // The `OK` after the lost sync is discarded before the retry, so the retry times out.
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OOPS\r\nOK\r\n"));
+        let error = fdcanusb
+            .transfer_with_retries(frame.clone(), false, 1)
+            .unwrap_err();
+        assert!(matches!(error, TransferError::Read(ReadError::Timeout(_))));
+        assert_eq!(fdcanusb.stats().lost_sync, 1);
+        assert_eq!(
+            fdcanusb.get_ref().tx.len(),
+            2 * b"can send 8001 01 E\n".len()
+        );
+
+        // An `OK` discarded before the retry is for a frame written without waiting for it
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OOPS\r\nOK\r\n"));
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        let error = fdcanusb.transfer_with_retries(frame, false, 1).unwrap_err();
+        assert!(error.is_timeout());
+        assert_eq!(fdcanusb.pending_acks(), 1);
+    }
+
+    #[test]
+    fn test_read_line_raw() {
+        let mut fdcanusb =
+            FdCanUSB::new(MockTransport::new(b"OK\r\nERR unknown\r\nrcv 0100 01\r\n"));
+        assert_eq!(fdcanusb.read_line_raw().unwrap(), b"OK");
+        assert_eq!(fdcanusb.read_line_raw().unwrap(), b"ERR unknown");
+        assert_eq!(fdcanusb.read_line_raw().unwrap(), b"rcv 0100 01");
+        assert!(matches!(
+            fdcanusb.read_line_raw(),
+            Err(ReadError::Timeout(_))
+        ));
+        assert_eq!(fdcanusb.stats().timeouts, 1);
+    }
+
+    #[test]
+    fn test_version() {
+        let mut fdcanusb =
+            FdCanUSB::new(MockTransport::new(b"fdcanusb 1.0\r\ngit abc123\r\nOK\r\n"));
+        assert_eq!(fdcanusb.version().unwrap(), "fdcanusb 1.0\ngit abc123");
+        assert_eq!(fdcanusb.get_ref().tx, b"version\n");
+
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"ERR unknown command\r\n"));
+        let error = fdcanusb.version().unwrap_err();
+        assert!(matches!(
+            error,
+            TransferError::Read(ReadError::DeviceError { command: Some(ref command), .. }) if command == "version"
+        ));
+    }
+
+    #[test]
+    fn test_write_returning() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\n"));
+        let frame = CanFdFrame::new(0x8001, &[1, 2]).unwrap();
+        let sent = fdcanusb.write_returning(frame).unwrap();
+        assert_eq!(sent.as_bytes(), fdcanusb.get_ref().tx);
+    }
+
+    #[test]
+    fn test_max_data_len() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\n"));
+        fdcanusb.set_max_data_len(8);
+        let error = fdcanusb
+            .write(CanFdFrame::new(0x8001, &[0; 12]).unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TransferError::InvalidFrameLength(InvalidFrameLength { len: 12, max: 8 })
+        ));
+        assert!(fdcanusb.get_ref().tx.is_empty());
+
+        let frames = [
+            CanFdFrame::new(0x8001, &[0; 8]).unwrap(),
+            CanFdFrame::new(0x8002, &[0; 9]).unwrap(),
+        ];
+        let error = fdcanusb.transfer_many(frames, 0).unwrap_err();
+        assert!(matches!(error, TransferError::Frame { index: 1, .. }));
+        assert_eq!(fdcanusb.pending_acks(), 1);
+    }
+
+    #[test]
+    fn test_incoming() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"rcv 0100 01\r\nOOPS\r\nrcv 0200 02\r\n",
+        ));
+        let frames: Vec<_> = fdcanusb.incoming().collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].as_ref().unwrap().data, [1]);
+        assert!(matches!(frames[1], Err(ReadError::LostSync { .. })));
+        assert_eq!(frames[2].as_ref().unwrap().data, [2]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"rcv 0100 01\r\nrcv 02"));
+        fdcanusb.read().unwrap();
+        assert_eq!(fdcanusb.buffered_len(), 6);
+        fdcanusb.compact();
+        assert_eq!(fdcanusb.buffered_len(), 6);
+        assert_eq!(&fdcanusb.buffer[..6], b"rcv 02");
+    }
+
+    #[test]
+    fn test_transfer() {
+        let transport = MockTransport::new(b"rcv 0100 01\r\nOK\r\nrcv 0100 02\r\nrcv 0100 03\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        let responses = fdcanusb.transfer(frame.clone(), 2).unwrap();
+        assert_eq!(responses[0].data, [1]);
+        assert_eq!(responses[1].data, [2]);
+
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nrcv 0100 01\r\n"));
+        match fdcanusb.transfer(frame, 3) {
+            Err(TransferError::Incomplete {
+                responses,
+                expected: 3,
+            }) => assert_eq!(responses.len(), 1),
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_max_line_len() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"rcv 0100 0102030405060708\r\n"));
+        fdcanusb.set_max_line_len(16);
+        assert!(matches!(
+            fdcanusb.read(),
+            Err(ReadError::LineTooLong { max: 16 })
+        ));
+
+        // A stuck device filling the whole buffer without a newline
+        let mut fdcanusb = FdCanUSB::new_with_buffer(MockTransport::new(&[b'x'; 64]), vec![0; 32]);
+        assert!(matches!(
+            fdcanusb.read_line_raw(),
+            Err(ReadError::LineTooLong { max: 32 })
+        ));
+        assert_eq!(fdcanusb.stats().parse_errors, 1);
+    }
+
+    #[test]
+    fn test_write_raw() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nERR bad\r\n"));
+        fdcanusb
+            .write_raw(FdCanUSBFrame::from("can send 8001 01 E b\n"))
+            .unwrap();
+        assert_eq!(fdcanusb.get_ref().tx, b"can send 8001 01 E b\n");
+        assert!(matches!(
+            fdcanusb.write_raw(FdCanUSBFrame::from("can sned\n")),
+            Err(TransferError::Read(ReadError::DeviceError { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_default_flags() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nOK\r\n"));
+        fdcanusb.set_default_flags(Some(false), Some(true), None);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap().with_brs(true))
+            .unwrap();
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 01 E b F\ncan send 8001 01 E B F\n"
+        );
+
+        fdcanusb.set_default_flags(None, Some(false), None);
+        assert!(matches!(
+            fdcanusb.write(CanFdFrame::new(0x8001, &[0; 12]).unwrap()),
+            Err(TransferError::InvalidFrameLength(InvalidFrameLength {
+                len: 12,
+                max: 8
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_host_timestamp() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"rcv 0100 01 t4294967290\r\nrcv 0100 02 t10\r\nrcv 0100 03\r\n",
+        ));
+        let epoch = std::time::SystemTime::UNIX_EPOCH;
+        fdcanusb.set_timestamp_epoch(epoch);
+        let frames: Vec<_> = fdcanusb.incoming().map(Result::unwrap).collect();
+        let times: Vec<_> = frames
+            .iter()
+            .map(|frame| fdcanusb.host_timestamp(frame))
+            .collect();
+        assert_eq!(
+            times,
+            [Some(epoch), Some(epoch + Duration::from_micros(16)), None]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_regression_handler() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"rcv 0100 01 t500\r\nOK\r\nrcv 0100 02 t20\r\nrcv 0100 03 t30\r\n",
+        ));
+        let (tx, rx) = std::sync::mpsc::channel();
+        fdcanusb.set_timestamp_regression_handler(move |previous, frame| {
+            tx.send((previous, frame.data.clone())).unwrap()
+        });
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        let frames: Vec<_> = fdcanusb.incoming().map(Result::unwrap).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [(500, vec![2])]);
+    }
+
+    #[test]
+    fn test_expect_response_from_id() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nrcv 0100 01\r\nOK\r\n"));
+        let query = CanFdFrame::new(0x8001, &[1]).unwrap();
+        let response = fdcanusb
+            .transfer_single(query, ExpectResponse::FromId)
+            .unwrap();
+        assert!(response.is_some());
+        let command = CanFdFrame::new(0x0001, &[1]).unwrap();
+        let response = fdcanusb
+            .transfer_single(command, ExpectResponse::FromId)
+            .unwrap();
+        assert!(response.is_none());
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut fdcanusb =
+            FdCanUSB::new(MockTransport::new(b"0304 B\r\ngarbage\r\nrcv 0100 01\r\n"));
+        assert!(matches!(fdcanusb.read(), Err(ReadError::LostSync { .. })));
+        assert_eq!(fdcanusb.resync().unwrap(), 9);
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+        assert!(matches!(fdcanusb.resync(), Err(ReadError::Timeout(_))));
+    }
+
+    #[test]
+    fn test_stream_reads() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"rcv 0100 01\r\nrcv 0100 02\r\nrcv 0100 03\r\n",
+        ));
+        let mut received = Vec::new();
+        fdcanusb
+            .stream_reads(|frame| {
+                received.push(frame.data[0]);
+                if frame.data[0] == 2 {
+                    std::ops::ControlFlow::Break(())
+                } else {
+                    std::ops::ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(received, [1, 2]);
+        let error = fdcanusb
+            .stream_reads(|_| std::ops::ControlFlow::Continue(()))
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+
+    #[test]
+    fn test_array_buffer_overflow() {
+        let mut rx = Vec::new();
+        for i in 0..8u8 {
+            rx.extend_from_slice(format!("rcv 01{i:02x} {i:02x}\r\n").as_bytes());
+        }
+        let transport = MockTransport::new(&rx);
+        let mut fdcanusb = FdCanUSB::<_, [u8; 32]>::new_with_array(transport);
+        for i in 0..8u8 {
+            let frame = fdcanusb.read().unwrap();
+            assert_eq!(frame.arbitration_id.as_raw(), 0x100 | i as u32);
+            assert_eq!(frame.data, [i]);
+        }
+    }
+
+    #[test]
+    fn test_read_timeout_follows_deadline() {
+        let transport = MockTransport::new(b"rcv 0100 01\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb.set_read_timeout = Some(|transport, timeout| {
+            transport.read_timeouts.push(timeout);
+            Ok(())
+        });
+        let timeout = Duration::from_millis(50);
+        fdcanusb.read_timeout(timeout).unwrap();
+        assert!(fdcanusb.read_timeout(timeout).unwrap_err().is_timeout());
+        let read_timeouts = &fdcanusb.get_ref().read_timeouts;
+        assert_eq!(read_timeouts.len(), 2);
+        assert!(read_timeouts
+            .iter()
+            .all(|t| *t <= timeout && *t >= Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_write_encoded_into_scratch() {
+        let transport = MockTransport::new(b"OK\r\nOK\r\nERR bad\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb.set_default_flags(Some(true), None, None);
+        let frame = CanFdFrame::new(0x8001, &[1, 2, 3]).unwrap();
+        fdcanusb.write_encoded_into_scratch(&frame).unwrap();
+        fdcanusb.write_encoded_into_scratch(&frame).unwrap();
+        let scratch_ptr = fdcanusb.scratch.as_ptr();
+        match fdcanusb.write_encoded_into_scratch(&frame) {
+            Err(TransferError::Read(ReadError::DeviceError { command, .. })) => {
+                assert_eq!(command.as_deref(), Some("can send 8001 010203 E B"));
+            }
+            result => panic!("unexpected {result:?}"),
+        }
+        assert_eq!(fdcanusb.scratch.as_ptr(), scratch_ptr);
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 010203 E B\n".repeat(3)
+        );
+        fdcanusb.set_max_data_len(2);
+        assert!(matches!(
+            fdcanusb.write_encoded_into_scratch(&frame),
+            Err(TransferError::InvalidFrameLength(_))
+        ));
+    }
+
+    #[test]
+    fn test_skip_echo() {
+        let transport = MockTransport::new(b"can send 8001 00 E\r\nOK\r\ncan send 8001 00 E\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[0]).unwrap())
+            .unwrap();
+        // Only lines read while waiting for an `OK` are taken as echoes
+        assert!(matches!(fdcanusb.read(), Err(ReadError::LostSync { .. })));
+    }
+
+    #[test]
+    fn test_line_ending() {
+        let transport = MockTransport::new(b"OK\r\nOK\r\nOK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        fdcanusb.write(frame.clone()).unwrap();
+        fdcanusb.set_line_ending(LineEnding::CrLf);
+        fdcanusb.write(frame.clone()).unwrap();
+        let mut batch = fdcanusb.batch();
+        batch.queue(frame).unwrap();
+        batch.flush().unwrap();
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 01 E\ncan send 8001 01 E\r\ncan send 8001 01 E\r\n"
+        );
+    }
+
+    #[test]
+    fn test_set_bitrate() {
+        let transport = MockTransport::new(b"OK\r\nOK\r\n1000000\r\nOK\r\n5000000\r\nOK\r\nOK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb.set_bitrate(1_000_000, 5_000_000).unwrap();
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"conf set can.bitrate 1000000\nconf set can.fd_bitrate 5000000\n\
+            conf get can.bitrate\nconf get can.fd_bitrate\nconf write\n"
+        );
+
+        let mut fdcanusb = FdCanUSB::new(MockTransport::default());
+        assert!(matches!(
+            fdcanusb.set_bitrate(1_000_000, 500_000),
+            Err(TransferError::UnsupportedConfig { .. })
+        ));
+        for (arbitration_bps, data_bps) in [
+            (0, 1_000_000),
+            (2_000_000, 5_000_000),
+            (1_000_000, 10_000_000),
+        ] {
+            assert!(matches!(
+                fdcanusb.set_bitrate(arbitration_bps, data_bps),
+                Err(TransferError::UnsupportedConfig { .. })
+            ));
+        }
+        assert!(fdcanusb.get_ref().tx.is_empty());
+
+        let transport = MockTransport::new(b"OK\r\nOK\r\n500000\r\nOK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        match fdcanusb.set_bitrate(1_000_000, 5_000_000) {
+            Err(TransferError::ConfigMismatch { key, received, .. }) => {
+                assert_eq!((key.as_str(), received.as_str()), ("can.bitrate", "500000"));
+            }
+            result => panic!("unexpected {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_write_returning_ack() {
+        let transport = MockTransport::new(b"OK\r\nOK 3 free=12\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        assert_eq!(
+            fdcanusb.write_returning_ack(frame.clone()).unwrap(),
+            Ack::default()
+        );
+        let ack = fdcanusb.write_returning_ack(frame).unwrap();
+        assert_eq!(ack.fields, ["3", "free=12"]);
+    }
+
+    #[test]
+    fn test_transfer_timed() {
+        let transport = MockTransport::new(b"OK\r\nrcv 0100 01\r\nOK\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        let before = std::time::Instant::now();
+        let (response, elapsed) = fdcanusb
+            .transfer_timed(CanFdFrame::new(0x8001, &[1]).unwrap(), true)
+            .unwrap();
+        assert_eq!(response.unwrap().arbitration_id.as_raw(), 0x0100);
+        assert!(elapsed <= before.elapsed());
+        let (response, _) = fdcanusb
+            .transfer_timed(
+                CanFdFrame::new(0x0001, &[1]).unwrap(),
+                ExpectResponse::FromId,
+            )
+            .unwrap();
+        assert!(response.is_none());
+    }
+
+    #[test]
+    fn test_skip_empty_lines() {
+        let transport = MockTransport::new(b"\r\nrcv 8001 00\r\n\r\nOK\r\n\nrcv 0100 01\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        assert_eq!(fdcanusb.read().unwrap().arbitration_id.as_raw(), 0x8001);
+        let response = fdcanusb
+            .transfer_single(CanFdFrame::new(0x8001, &[1]).unwrap(), true)
+            .unwrap();
+        assert_eq!(response.unwrap().arbitration_id.as_raw(), 0x0100);
+    }
+
+    #[test]
+    fn test_write_invalid_frame() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::default());
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap().with_remote(true);
+        assert!(matches!(
+            fdcanusb.write(frame.clone()),
+            Err(TransferError::InvalidFrame(
+                FrameValidationError::RemoteWithData
+            ))
+        ));
+        assert!(matches!(
+            fdcanusb.write_encoded_into_scratch(&frame),
+            Err(TransferError::InvalidFrame(
+                FrameValidationError::RemoteWithData
+            ))
+        ));
+        fdcanusb.set_default_flags(None, Some(false), None);
+        assert!(matches!(
+            fdcanusb.transfer_single(CanFdFrame::new(0x8001, &[1]).unwrap().with_brs(true), true),
+            Err(TransferError::InvalidFrame(
+                FrameValidationError::BrsWithoutFd
+            ))
+        ));
+        assert!(fdcanusb.get_ref().tx.is_empty());
+    }
+
+    /// A clock shared with a [`SlowTransport`], which advances it on each read.
+    #[derive(Debug, Clone)]
+    struct MockClock(std::sync::Arc<std::sync::Mutex<std::time::Instant>>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> std::time::Instant {
+            *self.0.lock().unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            *self.0.lock().unwrap() += duration;
+        }
+    }
+
+    /// A transport which never has data, and takes `step` of the [`MockClock`]'s time for each read.
+    #[derive(Debug)]
+    struct SlowTransport {
+        clock: MockClock,
+        step: Duration,
+        reads: usize,
+    }
+
+    impl std::io::Read for SlowTransport {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            *self.clock.0.lock().unwrap() += self.step;
+            self.reads += 1;
+            Ok(0)
+        }
+    }
+
+    impl std::io::Write for SlowTransport {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_mock_clock_timeout() {
+        let clock = MockClock(std::sync::Arc::new(std::sync::Mutex::new(
+            std::time::Instant::now(),
+        )));
+        let transport = SlowTransport {
+            clock: clock.clone(),
+            step: Duration::from_millis(100),
+            reads: 0,
+        };
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb.set_clock(clock);
+        let error = fdcanusb
+            .read_timeout(Duration::from_millis(500))
+            .unwrap_err();
+        assert!(error.is_timeout());
+        // Reads at 0 to 500ms are within the deadline, the deadline has passed after the read at 500ms
+        assert_eq!(fdcanusb.get_ref().reads, 6);
+    }
+
+    #[test]
+    fn test_read_invalid_utf8() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"rcv 8001 01 \xff\xfe\r\nrcv \xff01 01\r\n",
+        ));
+        let frame = fdcanusb.read().unwrap();
+        assert_eq!(frame.data, vec![1]);
+        assert!(matches!(
+            fdcanusb.read(),
+            Err(ReadError::Parse(ParseError::MalformedHex { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_ping() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"1000000\r\nOK\r\n"));
+        fdcanusb.ping().unwrap();
+        assert_eq!(fdcanusb.get_ref().tx, b"conf get can.bitrate\n");
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"ERR unknown command\r\n"));
+        fdcanusb.ping().unwrap();
+        let mut fdcanusb = FdCanUSB::new(MockTransport::default());
+        assert!(fdcanusb.ping().unwrap_err().is_timeout());
+    }
+
+    /// A non-blocking transport which accepts `capacity` more bytes.
+    #[derive(Debug, Default)]
+    struct ChokedTransport {
+        capacity: usize,
+        tx: Vec<u8>,
+    }
+
+    impl std::io::Read for ChokedTransport {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl std::io::Write for ChokedTransport {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.capacity);
+            if n == 0 {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            self.capacity -= n;
+            self.tx.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_try_write() {
+        let mut fdcanusb = FdCanUSB::new(ChokedTransport {
+            capacity: 9,
+            tx: Vec::new(),
+        });
+        let first = CanFdFrame::new(0x8001, &[1, 2]).unwrap();
+        let second = CanFdFrame::new(0x8002, &[3]).unwrap();
+        assert_eq!(fdcanusb.try_write(first).unwrap(), WriteStatus::Sent);
+        assert_eq!(fdcanusb.get_ref().tx, b"can send ");
+        assert_eq!(
+            fdcanusb.try_write(second.clone()).unwrap(),
+            WriteStatus::WouldBlock
+        );
+        fdcanusb.get_mut().capacity = 12;
+        // The first frame is finished before the second is started
+        assert_eq!(
+            fdcanusb.try_write(second.clone()).unwrap(),
+            WriteStatus::WouldBlock
+        );
+        assert_eq!(fdcanusb.get_ref().tx, b"can send 8001 0102 E\n");
+        fdcanusb.get_mut().capacity = 100;
+        assert_eq!(fdcanusb.try_write(second).unwrap(), WriteStatus::Sent);
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 0102 E\ncan send 8002 03 E\n"
+        );
+        assert_eq!(fdcanusb.try_write_unsent().unwrap(), WriteStatus::Sent);
+    }
+
+    #[test]
+    fn test_min_interframe_gap() {
+        let start = std::time::Instant::now();
+        let clock = MockClock(std::sync::Arc::new(std::sync::Mutex::new(start)));
+        let mut fdcanusb = FdCanUSB::new(MockTransport::default());
+        fdcanusb.set_clock(clock.clone());
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        fdcanusb.set_min_interframe_gap(Duration::from_millis(20));
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        assert_eq!(clock.now(), start);
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        assert_eq!(clock.now(), start + Duration::from_millis(20));
+        let mut batch = fdcanusb.batch();
+        batch.queue(frame).unwrap();
+        batch.queue(CanFdFrame::new(0x8002, &[1]).unwrap()).unwrap();
+        // The batch has no acknowledgments to read
+        assert!(batch.flush().is_err());
+        assert_eq!(clock.now(), start + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_recent_bytes() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"rcv 0100 01\r\nOK\r\n"));
+        fdcanusb.read().unwrap();
+        assert!(matches!(fdcanusb.read(), Err(ReadError::LostSync { .. })));
+        assert_eq!(fdcanusb.recent_bytes(), b"rcv 0100 01\r\nOK\r\n");
+        fdcanusb.set_recent_bytes_len(4);
+        assert_eq!(fdcanusb.recent_bytes(), b"OK\r\n");
+        fdcanusb.set_recent_bytes_len(0);
+        assert!(fdcanusb.recent_bytes().is_empty());
+    }
+
+    #[test]
+    fn test_read_into() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"rcv 0100 01 E\r\n\r\nOK\r\n"));
+        assert_eq!(fdcanusb.read_into().unwrap(), b"rcv 0100 01 E");
+        assert!(matches!(
+            fdcanusb.read_into(),
+            Err(ReadError::LostSync { received, .. }) if received == "OK"
+        ));
+        assert_eq!(fdcanusb.stats().frames_received, 1);
+    }
+
+    #[test]
+    fn test_read_from() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"rcv 0100 01\r\nrcv 0200 02\r\n"));
+        assert_eq!(fdcanusb.read_from(2).unwrap().data, [2]);
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+        assert!(fdcanusb.read_from(3).unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn test_pending_acks() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"OK\r\nOK\r\nOK\r\nrcv 0100 01\r\nOK\r\nERR bad\r\nOK\r\n",
+        ));
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        for _ in 0..3 {
+            fdcanusb.write_no_ack(frame.clone()).unwrap();
+        }
+        assert_eq!(fdcanusb.pending_acks(), 3);
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+        assert_eq!(fdcanusb.pending_acks(), 0);
+        // A write reads the earlier `OK`s first, and an earlier `ERR` is not its own
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        fdcanusb.write_no_ack(frame.clone()).unwrap();
+        fdcanusb.write(frame).unwrap();
+        assert_eq!(fdcanusb.pending_acks(), 0);
+        assert_eq!(fdcanusb.stats().device_errors, 1);
+    }
+
+    #[test]
+    fn test_no_response() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\n"));
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        match fdcanusb.transfer_single(frame.clone(), true) {
+            Err(TransferError::NoResponse { source }) => {
+                assert_eq!(source.kind(), std::io::ErrorKind::TimedOut)
+            }
+            other => panic!("expected NoResponse, got {other:?}"),
+        }
+
+        // Part of a response was read, so the device did reply
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nrcv 0100 0"));
+        assert!(matches!(
+            fdcanusb.transfer_single(frame, true),
+            Err(TransferError::Read(ReadError::Timeout(_)))
+        ));
+    }
+
+    #[test]
+    fn test_log_frames() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nrcv 0100 01\r\n"));
+        assert!(fdcanusb.log_frames());
+        fdcanusb.set_log_frames(false);
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        let response = fdcanusb.transfer_single(frame, true).unwrap();
+        assert_eq!(response.unwrap().data, [1]);
+    }
+
+    #[test]
+    fn test_wait_for_ready() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"OOPS\r\nrcv 0100 01\r\nFDCANUSB 2024-01-01\r\nrcv 0100 02\r\nfdcanusb 2024-01-02\r\n",
+        ));
+        fdcanusb
+            .write_no_ack(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        let banner = fdcanusb.wait_for_ready(Duration::from_millis(10)).unwrap();
+        assert_eq!(banner, "FDCANUSB 2024-01-01");
+        assert_eq!(fdcanusb.banner(), Some("FDCANUSB 2024-01-01"));
+        assert_eq!(fdcanusb.pending_acks(), 0);
+        // A frame received before the banner is kept
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+        assert_eq!(fdcanusb.read().unwrap().data, [2]);
+        // Only `wait_for_ready` skips a banner
+        assert!(matches!(fdcanusb.read(), Err(ReadError::LostSync { .. })));
+        assert!(matches!(
+            fdcanusb.wait_for_ready(Duration::from_millis(10)),
+            Err(TransferError::Read(ReadError::Timeout(_)))
+        ));
+    }
+
+    #[test]
+    fn test_rcv_before_ok() {
+        let transport = MockTransport::new(b"rcv 0100 01\r\nOK\r\nrcv 0100 02\r\n");
+        let mut fdcanusb = FdCanUSB::new(transport);
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .expect("Failed to write frame");
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![1]);
+        assert_eq!(fdcanusb.read().expect("Failed to read frame").data, vec![2]);
     }
 }
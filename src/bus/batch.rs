@@ -0,0 +1,193 @@
+use super::{log_tx, with_command, Direction, FdCanUSB};
+use crate::error::{TransferError, WriteError};
+use crate::frames::{CanFdFrame, FdCanUSBFrame};
+
+/// Queues frames to write them to a [`FdCanUSB`] in a single write, created with [`FdCanUSB::batch`].
+///
+/// This avoids a round trip for each frame, which is useful in high rate control loops.
+/// Queued frames are only written by [`BatchWriter::flush`], and are discarded if the `BatchWriter` is dropped.
+///
+/// Frames can be queued with a priority, so that e.g. a stop command is written before the rest of a backed up queue.
+/// This only orders the frames written by the host: on the bus, CAN arbitration already gives the frame with the lowest id priority.
+#[derive(Debug)]
+pub struct BatchWriter<'a, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fdcanusb: &'a mut FdCanUSB<T, Buffer>,
+    /// The queued frames, with their priority
+    frames: Vec<(u8, FdCanUSBFrame)>,
+}
+
+impl<T, Buffer> BatchWriter<'_, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Queue a frame to be written by the next [`BatchWriter::flush`].
+    ///
+    /// The frame is encoded immediately, so an invalid frame is rejected here rather than when flushing.
+    pub fn queue(&mut self, frame: CanFdFrame) -> Result<(), TransferError> {
+        self.queue_with_priority(frame, 0)
+    }
+
+    /// Queue a frame like [`BatchWriter::queue`], to be written before any frame with a lower `priority`.
+    ///
+    /// Frames with the same priority are written in the order they were queued, and [`BatchWriter::queue`] uses a priority of 0.
+    pub fn queue_with_priority(
+        &mut self,
+        frame: CanFdFrame,
+        priority: u8,
+    ) -> Result<(), TransferError> {
+        self.frames.push((priority, self.fdcanusb.encode(frame)?));
+        Ok(())
+    }
+
+    /// The number of frames queued.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if no frames are queued.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Write every queued frame in a single write, highest priority first, then read an `OK` for each of them.
+    ///
+    /// The FdCanUSB acknowledges frames in the order they were written,
+    /// so if a frame is rejected or an `OK` does not arrive the error is returned as [`TransferError::Frame`]
+    /// with the `index` of the frame, in the order they were queued. The frames written after it were also sent,
+    /// and their `OK`s are counted in [`FdCanUSB::pending_acks`], to be read later.
+    /// `rcv` frames received while reading the acknowledgments are queued for later reads.
+    pub fn flush(&mut self) -> Result<(), TransferError> {
+        let mut queued: Vec<_> = std::mem::take(&mut self.frames)
+            .into_iter()
+            .enumerate()
+            .collect();
+        if queued.is_empty() {
+            return Ok(());
+        }
+        // A stable sort keeps frames of the same priority in the order they were queued
+        queued.sort_by_key(|(_, (priority, _))| std::cmp::Reverse(*priority));
+        let (indices, frames): (Vec<_>, Vec<_>) = queued
+            .into_iter()
+            .map(|(index, (_, frame))| (index, frame))
+            .unzip();
+        self.fdcanusb.write_batch(&frames)?;
+        self.fdcanusb.compact();
+        for (written, (index, frame)) in indices.into_iter().zip(&frames).enumerate() {
+            if let Err(e) = self.fdcanusb.read_ok() {
+                self.fdcanusb.pending_acks += frames.len() - written - 1;
+                return Err(TransferError::Frame {
+                    index,
+                    source: Box::new(with_command(e, frame.as_bytes()).into()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, Buffer> FdCanUSB<T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Create a [`BatchWriter`], to queue frames and write them in a single write.
+    pub fn batch(&mut self) -> BatchWriter<'_, T, Buffer> {
+        BatchWriter {
+            fdcanusb: self,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Write `frames` with a single call to the transport.
+    fn write_batch(&mut self, frames: &[FdCanUSBFrame]) -> Result<(), WriteError> {
+        let mut bytes = Vec::with_capacity(frames.iter().map(|frame| frame.as_bytes().len()).sum());
+        for frame in frames {
+            self.line_ending.push_line(&mut bytes, frame.as_bytes());
+        }
+        self.write_unsent()?;
+        self.wait_interframe_gap();
+        self.last_write_at = Some(self.now());
+        self.transport.write_all(&bytes)?;
+        if self.log_frames {
+            for frame in frames {
+                log_tx(frame.as_bytes());
+            }
+        }
+        self.last_written.clone_from(&bytes);
+        if let Some(logger) = &mut self.raw_logger {
+            (logger.0)(Direction::Tx, &bytes);
+        }
+        self.stats.frames_sent += frames.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::MockTransport;
+    use super::*;
+    use crate::error::ReadError;
+
+    #[test]
+    fn test_batch_writer() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nOK\r\nrcv 0100 01\r\nOK\r\n"));
+        let mut batch = fdcanusb.batch();
+        for id in [0x8001, 0x8002, 0x8003] {
+            batch.queue(CanFdFrame::new(id, &[1]).unwrap()).unwrap();
+        }
+        assert_eq!(batch.len(), 3);
+        batch.flush().unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 01 E\ncan send 8002 01 E\ncan send 8003 01 E\n"
+        );
+        assert_eq!(fdcanusb.stats().frames_sent, 3);
+        assert_eq!(fdcanusb.read().unwrap().data, [1]);
+    }
+
+    #[test]
+    fn test_batch_writer_priority() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nERR bad\r\nOK\r\n"));
+        let mut batch = fdcanusb.batch();
+        batch.queue(CanFdFrame::new(0x8001, &[1]).unwrap()).unwrap();
+        batch.queue(CanFdFrame::new(0x8002, &[1]).unwrap()).unwrap();
+        batch
+            .queue_with_priority(CanFdFrame::new(0x8003, &[1]).unwrap(), 1)
+            .unwrap();
+        // The second frame written is rejected, which was queued first
+        assert!(matches!(
+            batch.flush(),
+            Err(TransferError::Frame { index: 0, .. })
+        ));
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8003 01 E\ncan send 8001 01 E\ncan send 8002 01 E\n"
+        );
+    }
+
+    #[test]
+    fn test_batch_writer_reports_failed_index() {
+        let mut fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nERR bad\r\nOK\r\n"));
+        let mut batch = fdcanusb.batch();
+        for id in [0x8001, 0x8002, 0x8003] {
+            batch.queue(CanFdFrame::new(id, &[1]).unwrap()).unwrap();
+        }
+        match batch.flush() {
+            Err(TransferError::Frame { index: 1, source }) => assert!(matches!(
+                *source,
+                TransferError::Read(ReadError::DeviceError { command: Some(ref command), .. })
+                    if command == "can send 8002 01 E"
+            )),
+            result => panic!("unexpected result {result:?}"),
+        }
+        assert_eq!(fdcanusb.pending_acks(), 1);
+        assert!(fdcanusb.read_available().unwrap().is_empty());
+        assert_eq!(fdcanusb.pending_acks(), 0);
+    }
+}
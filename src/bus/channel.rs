@@ -0,0 +1,217 @@
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use super::FdCanUSB;
+use crate::error::{TransferError, WriteError};
+use crate::frames::CanFdFrame;
+
+/// A frame sent to the writer thread, with its priority and the channel its result is returned on.
+type Request = (u8, CanFdFrame, mpsc::Sender<Result<(), TransferError>>);
+
+/// Sends frames to be written by the thread owning a [`FdCanUSB`], created with [`FdCanUSB::into_channel`].
+///
+/// The sender can be cloned, so frames can be written from many threads without a lock.
+#[derive(Debug, Clone)]
+pub struct FrameSender {
+    sender: mpsc::SyncSender<Request>,
+}
+
+impl FrameSender {
+    /// Queue a frame to be written, returning a [`PendingWrite`] to wait for the result of the write.
+    ///
+    /// If the channel is full, this blocks until the writer thread takes a frame,
+    /// so producers are slowed to the rate frames can be written.
+    /// Returns an `Err` if the writer thread has stopped.
+    pub fn send(&self, frame: CanFdFrame) -> Result<PendingWrite, TransferError> {
+        self.send_with_priority(frame, 0)
+    }
+
+    /// Queue a frame like [`FrameSender::send`], to be written before any queued frame with a lower `priority`.
+    ///
+    /// Frames with the same priority are written in the order they were sent, and [`FrameSender::send`] uses a priority of 0.
+    /// This only orders the frames waiting for the writer thread: on the bus, CAN arbitration already gives the frame with the lowest id priority.
+    pub fn send_with_priority(
+        &self,
+        frame: CanFdFrame,
+        priority: u8,
+    ) -> Result<PendingWrite, TransferError> {
+        let (result, receiver) = mpsc::channel();
+        self.sender
+            .send((priority, frame, result))
+            .map_err(|_| writer_stopped())?;
+        Ok(PendingWrite { receiver })
+    }
+}
+
+/// The result of a write queued with [`FrameSender::send`].
+#[derive(Debug)]
+pub struct PendingWrite {
+    receiver: mpsc::Receiver<Result<(), TransferError>>,
+}
+
+impl PendingWrite {
+    /// Wait for the frame to be written and acknowledged, see [`FdCanUSB::write`].
+    ///
+    /// Returns an `Err` if the writer thread stopped before writing the frame.
+    pub fn wait(self) -> Result<(), TransferError> {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| Err(writer_stopped()))
+    }
+}
+
+/// The index of the earliest request with the highest priority in a non-empty `queue`.
+fn highest_priority(queue: &[Request]) -> usize {
+    // `max_by_key` returns the last of equal elements, so the earliest is found in reverse
+    (0..queue.len())
+        .rev()
+        .max_by_key(|&i| queue[i].0)
+        .expect("the queue is not empty")
+}
+
+/// The writer thread has stopped, e.g. because it panicked.
+fn writer_stopped() -> TransferError {
+    WriteError::from(std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "FdCanUSB writer thread stopped",
+    ))
+    .into()
+}
+
+impl<T, Buffer> FdCanUSB<T, Buffer>
+where
+    T: std::io::Write + std::io::Read + Send + 'static,
+    Buffer: AsRef<[u8]> + AsMut<[u8]> + Send + 'static,
+{
+    /// Move the [FdCanUSB] to a thread which writes the frames sent with the returned [`FrameSender`], in the order they are sent.
+    ///
+    /// This serialises access to the serial port for many producers, without a lock.
+    /// Frames sent with [`FrameSender::send_with_priority`] are written before frames with a lower priority which are waiting.
+    /// Up to `capacity` frames are queued before [`FrameSender::send`] blocks, and a `capacity` of 0 makes each send wait for the thread to take the frame.
+    ///
+    /// The thread stops once every `FrameSender` is dropped, returning the `FdCanUSB` through the [`JoinHandle`].
+    pub fn into_channel(self, capacity: usize) -> (FrameSender, JoinHandle<Self>) {
+        let (sender, receiver) = mpsc::sync_channel::<Request>(capacity);
+        let mut fdcanusb = self;
+        let handle = std::thread::spawn(move || {
+            // Frames taken from the channel, so the one with the highest priority can be written next
+            let mut queue = Vec::new();
+            loop {
+                if queue.is_empty() {
+                    match receiver.recv() {
+                        Ok(request) => queue.push(request),
+                        Err(_) => break,
+                    }
+                }
+                // Only as many frames as the channel holds are taken, so producers still block when it is full
+                while queue.len() < capacity.max(1) {
+                    match receiver.try_recv() {
+                        Ok(request) => queue.push(request),
+                        Err(_) => break,
+                    }
+                }
+                let (_, frame, result) = queue.remove(highest_priority(&queue));
+                // The producer may no longer be waiting for the result
+                let _ = result.send(fdcanusb.write(frame));
+            }
+            fdcanusb
+        });
+        (FrameSender { sender }, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::MockTransport;
+    use super::*;
+
+    #[test]
+    fn test_into_channel() {
+        let fdcanusb = FdCanUSB::new(MockTransport::new(b"OK\r\nERR bad\r\n"));
+        let (sender, handle) = fdcanusb.into_channel(1);
+        let first = sender.send(CanFdFrame::new(0x8001, &[1]).unwrap()).unwrap();
+        let other = sender.clone();
+        let second = std::thread::spawn(move || {
+            other
+                .send(CanFdFrame::new(0x8002, &[2]).unwrap())
+                .unwrap()
+                .wait()
+        });
+        first.wait().unwrap();
+        assert!(second.join().unwrap().is_err());
+        drop(sender);
+        let fdcanusb = handle.join().unwrap();
+        assert_eq!(
+            fdcanusb.get_ref().tx,
+            b"can send 8001 01 E\ncan send 8002 02 E\n"
+        );
+    }
+
+    /// A [`MockTransport`] whose first read waits to be released, holding the writer thread mid-write.
+    struct GatedTransport {
+        inner: MockTransport,
+        /// Signalled when the first read starts, and then waited on.
+        gate: Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>,
+    }
+
+    impl std::io::Read for GatedTransport {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if let Some((reading, release)) = self.gate.take() {
+                reading.send(()).unwrap();
+                release.recv().unwrap();
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl std::io::Write for GatedTransport {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn test_into_channel_full() {
+        let (reading, reading_receiver) = mpsc::channel();
+        let (release_sender, release) = mpsc::channel();
+        let transport = GatedTransport {
+            inner: MockTransport::new(b"OK\r\nOK\r\n"),
+            gate: Some((reading, release)),
+        };
+        let (sender, handle) = FdCanUSB::new(transport).into_channel(1);
+        let first = sender.send(CanFdFrame::new(0x8001, &[1]).unwrap()).unwrap();
+        reading_receiver.recv().unwrap();
+
+        // The writer thread is busy with the first frame, so the channel holds one more and then blocks
+        let second = sender.send(CanFdFrame::new(0x8002, &[2]).unwrap()).unwrap();
+        let (result, _) = mpsc::channel();
+        assert!(matches!(
+            sender
+                .sender
+                .try_send((0, CanFdFrame::new(0x8003, &[3]).unwrap(), result)),
+            Err(mpsc::TrySendError::Full(_))
+        ));
+
+        release_sender.send(()).unwrap();
+        first.wait().unwrap();
+        second.wait().unwrap();
+        drop(sender);
+        let fdcanusb = handle.join().unwrap();
+        assert_eq!(
+            fdcanusb.get_ref().inner.tx,
+            b"can send 8001 01 E\ncan send 8002 02 E\n"
+        );
+    }
+
+    #[test]
+    fn test_highest_priority() {
+        let request = |priority| (priority, CanFdFrame::default(), mpsc::channel().0);
+        let queue = [request(0), request(2), request(1), request(2)];
+        assert_eq!(highest_priority(&queue), 1);
+        assert_eq!(highest_priority(&queue[..1]), 0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use super::{parse_rcv, unexpected_packet, FdCanUSB, DEFAULT_TIMEOUT};
+use crate::error::{ReadError, TransferError};
+use crate::frames::{CanFdFrame, FdCanUSBFrame};
+use crate::stats::Stats;
+
+/// The write half of a [`FdCanUSB`], created with [`FdCanUSB::split`] or [`FdCanUSB::split_with`].
+///
+/// Frames are written without waiting for their `OK`, as the acknowledgments are read by the [`FdCanUSBReader`].
+#[derive(Debug)]
+pub struct FdCanUSBWriter<W>
+where
+    W: std::io::Write + std::io::Read,
+{
+    fdcanusb: FdCanUSB<W, Vec<u8>>,
+}
+
+impl<W> FdCanUSBWriter<W>
+where
+    W: std::io::Write + std::io::Read,
+{
+    /// Write a frame. The `OK`, or `ERR`, is read by the [`FdCanUSBReader`].
+    pub fn write(&mut self, frame: CanFdFrame) -> Result<(), TransferError> {
+        self.fdcanusb.write_no_ack(frame)
+    }
+
+    /// Write a pre-encoded frame. The `OK`, or `ERR`, is read by the [`FdCanUSBReader`].
+    pub fn write_raw(&mut self, frame: FdCanUSBFrame) -> Result<(), TransferError> {
+        self.fdcanusb.write_frame(&frame)?;
+        Ok(())
+    }
+
+    /// The counters of the frames written.
+    pub fn stats(&self) -> &Stats {
+        self.fdcanusb.stats()
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &W {
+        self.fdcanusb.get_ref()
+    }
+}
+
+/// The read half of a [`FdCanUSB`], created with [`FdCanUSB::split`] or [`FdCanUSB::split_with`].
+///
+/// The `OK` acknowledgments for frames written by the [`FdCanUSBWriter`] arrive in the same stream as the `rcv` frames,
+/// so they are skipped and counted, see [`FdCanUSBReader::acks`].
+/// An `ERR` reply is returned as [`ReadError::DeviceError`], but it can not be matched to the frame that caused it.
+#[derive(Debug)]
+pub struct FdCanUSBReader<T, Buffer = Vec<u8>>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fdcanusb: FdCanUSB<T, Buffer>,
+    acks: u64,
+}
+
+impl<T, Buffer> FdCanUSBReader<T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Read a frame, skipping any `OK` acknowledgments.
+    pub fn read(&mut self) -> Result<CanFdFrame, ReadError> {
+        self.read_timeout(DEFAULT_TIMEOUT)
+    }
+
+    /// Read a frame, skipping any `OK` acknowledgments, waiting up to `timeout` for it to arrive.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<CanFdFrame, ReadError> {
+        if let Some(response) = self.fdcanusb.pending.pop_front() {
+            self.fdcanusb.stats.frames_received += 1;
+            return Ok(response);
+        }
+        let deadline = self.fdcanusb.now() + timeout;
+        let log_frames = self.fdcanusb.log_frames;
+        loop {
+            let timeout = deadline.saturating_duration_since(self.fdcanusb.now());
+            let response = match self.fdcanusb.read_packet(timeout) {
+                Ok(packet) if packet.starts_with(b"OK") => {
+                    self.acks += 1;
+                    continue;
+                }
+                Ok(packet) if packet.starts_with(b"rcv") => parse_rcv(packet, log_frames),
+                Ok(packet) => Err(unexpected_packet("rcv or OK", packet)),
+                Err(e) => Err(e),
+            };
+            let response = response.inspect_err(|e| self.fdcanusb.stats.record_error(e))?;
+            self.fdcanusb.check_timestamp(&response);
+            self.fdcanusb.stats.frames_received += 1;
+            return Ok(response);
+        }
+    }
+
+    /// The number of `OK` acknowledgments read, one for each frame the FdCanUSB accepted.
+    pub fn acks(&self) -> u64 {
+        self.acks
+    }
+
+    /// The counters of the frames read and errors seen.
+    pub fn stats(&self) -> &Stats {
+        self.fdcanusb.stats()
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        self.fdcanusb.get_ref()
+    }
+}
+
+impl<T, Buffer> FdCanUSB<T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Split into a write half, using `writer`, and a read half, using the current transport,
+    /// so that frames can be written and read from different threads.
+    ///
+    /// `writer` must write to the same device, e.g. a clone of the transport.
+    /// The writer keeps the maximum data length, default flags and whether frames are logged, and any buffered or queued reads stay with the reader.
+    /// See [`FdCanUSBReader`] for how acknowledgments are handled.
+    pub fn split_with<W>(self, writer: W) -> (FdCanUSBWriter<W>, FdCanUSBReader<T, Buffer>)
+    where
+        W: std::io::Write + std::io::Read,
+    {
+        let mut write_half = FdCanUSB::new_with_buffer(writer, Vec::new());
+        write_half.max_data_len = self.max_data_len;
+        write_half.default_flags = self.default_flags;
+        write_half.log_frames = self.log_frames;
+        // The rest of a frame partly written by `try_write` is written by the writer's next write
+        write_half.last_written.clone_from(&self.last_written);
+        write_half.unsent = self.unsent;
+        (
+            FdCanUSBWriter {
+                fdcanusb: write_half,
+            },
+            FdCanUSBReader {
+                fdcanusb: self,
+                acks: 0,
+            },
+        )
+    }
+}
+
+#[cfg(feature = "serial2")]
+impl<Buffer> FdCanUSB<serial2::SerialPort, Buffer>
+where
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Split into a write half and a read half, so that frames can be written and read from different threads.
+    ///
+    /// The write half uses a clone of the serial port, see [`FdCanUSB::split_with`].
+    pub fn split(
+        self,
+    ) -> std::io::Result<(
+        FdCanUSBWriter<serial2::SerialPort>,
+        FdCanUSBReader<serial2::SerialPort, Buffer>,
+    )> {
+        let writer = self.transport.try_clone()?;
+        Ok(self.split_with(writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::MockTransport;
+    use super::*;
+
+    #[test]
+    fn test_split_with() {
+        let fdcanusb = FdCanUSB::new(MockTransport::new(
+            b"OK\r\nrcv 0100 01\r\nOK\r\nERR bad\r\nrcv 0100 02\r\n",
+        ));
+        let (mut writer, mut reader) = fdcanusb.split_with(MockTransport::default());
+        let writer = std::thread::spawn(move || {
+            let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+            writer.write(frame.clone()).unwrap();
+            writer.write(frame).unwrap();
+            writer
+        })
+        .join()
+        .unwrap();
+        assert_eq!(
+            writer.get_ref().tx,
+            b"can send 8001 01 E\ncan send 8001 01 E\n"
+        );
+
+        assert_eq!(reader.read().unwrap().data, [1]);
+        assert!(matches!(reader.read(), Err(ReadError::DeviceError { .. })));
+        assert_eq!(reader.read().unwrap().data, [2]);
+        assert_eq!(reader.acks(), 2);
+    }
+}
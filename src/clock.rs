@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::frames::CanFdFrame;
+
+/// The source of the current time for the timeouts of an [`FdCanUSB`](crate::FdCanUSB).
+///
+/// The default is the [`SystemClock`]. A clock which is advanced manually can be set with
+/// [`FdCanUSB::set_clock`](crate::FdCanUSB::set_clock), to test timeouts without waiting for them.
+pub trait Clock: std::fmt::Debug + Send {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Wait for `duration`, e.g. for the [minimum inter-frame gap](crate::FdCanUSB::set_min_interframe_gap).
+    ///
+    /// The default sleeps the current thread, and a manual clock can advance itself instead.
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// The system's monotonic clock, using [`Instant::now`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The number of microseconds before the FdCanUSB's `u32` timestamp wraps, about 71 minutes.
+const WRAP_MICROS: u64 = 1 << 32;
+
+/// Converts device timestamps into host times, unwrapping the `u32` microsecond counter.
+#[derive(Debug, Default)]
+pub(crate) struct DeviceClock {
+    /// The host time and the device timestamp it is paired with.
+    anchor: Option<(SystemTime, u32)>,
+    /// The host time to pair with the next device timestamp.
+    epoch: Option<SystemTime>,
+    /// The last device timestamp seen, used to detect wraps.
+    last: u32,
+    /// The number of times the device timestamp has wrapped since the anchor.
+    wraps: u64,
+}
+
+impl DeviceClock {
+    /// Pair the next device timestamp with `epoch`, restarting the clock.
+    pub(crate) fn set_epoch(&mut self, epoch: SystemTime) {
+        *self = DeviceClock {
+            epoch: Some(epoch),
+            ..Default::default()
+        };
+    }
+
+    /// Convert a device timestamp into a host time.
+    ///
+    /// The first timestamp is paired with the epoch, or with the current time if no epoch is set.
+    /// A timestamp lower than the last one means the counter has wrapped.
+    pub(crate) fn host_time(&mut self, timestamp: u32) -> SystemTime {
+        let (anchor_time, anchor_timestamp) = *self
+            .anchor
+            .get_or_insert_with(|| (self.epoch.take().unwrap_or_else(SystemTime::now), timestamp));
+        if timestamp < self.last {
+            self.wraps += 1;
+        }
+        self.last = timestamp;
+        let micros = self.wraps * WRAP_MICROS + u64::from(timestamp) - u64::from(anchor_timestamp);
+        anchor_time + Duration::from_micros(micros)
+    }
+}
+
+/// A handler installed with [`FdCanUSB::set_timestamp_regression_handler`](crate::FdCanUSB::set_timestamp_regression_handler),
+/// called with the previous timestamp and the frame whose timestamp is earlier.
+type RegressionHandler = dyn FnMut(u32, &CanFdFrame) + Send;
+
+/// Checks the timestamps of received frames only move forward, allowing for the `u32` microsecond counter wrapping.
+pub(crate) struct TimestampCheck {
+    handler: Box<RegressionHandler>,
+    /// The timestamp of the last frame checked.
+    last: Option<u32>,
+}
+
+impl TimestampCheck {
+    pub(crate) fn new(handler: Box<RegressionHandler>) -> Self {
+        TimestampCheck {
+            handler,
+            last: None,
+        }
+    }
+
+    /// Check the timestamp of `frame` is not earlier than the last one, calling the handler if it is.
+    ///
+    /// A step forward of more than half the counter's range is taken as a step back,
+    /// so a frame received after the counter wrapped is not a regression.
+    pub(crate) fn check(&mut self, frame: &CanFdFrame) {
+        let Some(timestamp) = frame.timestamp else {
+            return;
+        };
+        if let Some(last) = self.last.replace(timestamp) {
+            if timestamp.wrapping_sub(last) > u32::MAX / 2 {
+                (self.handler)(last, frame);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for TimestampCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimestampCheck")
+            .field("last", &self.last)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_clock_wraps() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        let mut clock = DeviceClock::default();
+        clock.set_epoch(epoch);
+        assert_eq!(clock.host_time(u32::MAX - 10), epoch);
+        assert_eq!(clock.host_time(u32::MAX), epoch + Duration::from_micros(10));
+        assert_eq!(clock.host_time(5), epoch + Duration::from_micros(16));
+    }
+
+    #[test]
+    fn test_timestamp_check() {
+        let regressions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = regressions.clone();
+        let mut check = TimestampCheck::new(Box::new(move |previous, frame: &CanFdFrame| {
+            seen.lock()
+                .unwrap()
+                .push((previous, frame.timestamp.unwrap()))
+        }));
+        let frame = CanFdFrame::default();
+        for timestamp in [u32::MAX - 10, 5, 100, 100, 50, 60] {
+            check.check(&frame.clone().with_timestamp(timestamp));
+        }
+        check.check(&frame);
+        assert_eq!(*regressions.lock().unwrap(), [(100, 50)]);
+    }
+}
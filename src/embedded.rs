@@ -0,0 +1,97 @@
+use crate::FdCanUSB;
+
+/// Adapts a transport implementing the [`embedded_io`] traits to the [`std::io`] traits,
+/// so it can be used with a [`FdCanUSB`].
+///
+/// Errors from the transport are converted to a [`std::io::Error`] with the matching [`std::io::ErrorKind`],
+/// and are returned as [`ReadError`](crate::ReadError) and [`WriteError`](crate::WriteError) like any other IO error.
+/// Enable with the `embedded-io` feature.
+#[derive(Debug)]
+pub struct EmbeddedIo<T>(pub T);
+
+impl<T> EmbeddedIo<T> {
+    /// Consume the adapter, returning the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: embedded_io::Read> std::io::Read for EmbeddedIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf).map_err(to_io_error)
+    }
+}
+
+impl<T: embedded_io::Write> std::io::Write for EmbeddedIo<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf).map_err(to_io_error)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().map_err(to_io_error)
+    }
+}
+
+fn to_io_error(e: impl embedded_io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind().into(), format!("{e:?}"))
+}
+
+impl<T> FdCanUSB<EmbeddedIo<T>, Vec<u8>>
+where
+    T: embedded_io::Read + embedded_io::Write,
+{
+    /// Create a new [FdCanUSB] instance, with a transport implementing the [`embedded_io`] traits.
+    pub fn new_embedded_io(transport: T) -> Self {
+        Self::new(EmbeddedIo(transport))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanFdFrame;
+
+    struct MockTransport {
+        rx: &'static [u8],
+    }
+
+    impl embedded_io::ErrorType for MockTransport {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl embedded_io::Read for MockTransport {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.rx.is_empty() {
+                return Err(embedded_io::ErrorKind::TimedOut);
+            }
+            let len = buf.len().min(self.rx.len());
+            buf[..len].copy_from_slice(&self.rx[..len]);
+            self.rx = &self.rx[len..];
+            Ok(len)
+        }
+    }
+
+    impl embedded_io::Write for MockTransport {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_embedded_io_transfer() {
+        let transport = MockTransport {
+            rx: b"OK\r\nrcv 0100 01\r\n",
+        };
+        let mut fdcanusb = FdCanUSB::new_embedded_io(transport);
+        let response = fdcanusb
+            .transfer_single(CanFdFrame::new(0x8001, &[1]).unwrap(), true)
+            .expect("Failed to transfer frame");
+        assert_eq!(response.unwrap().data, vec![1]);
+        let error = fdcanusb.read().unwrap_err();
+        assert!(matches!(error, crate::ReadError::Timeout(_)));
+    }
+}
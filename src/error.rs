@@ -1,47 +1,416 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
 use thiserror::Error;
 
+#[cfg(feature = "std")]
+use crate::frames::CanFdFrame;
+
+#[cfg(feature = "std")]
 #[derive(Error, Debug)]
 pub enum TransferError {
     #[error(transparent)]
     Write(#[from] WriteError),
     #[error(transparent)]
     Read(#[from] ReadError),
+    /// The frame has more data than the [`FdCanUSB`](crate::FdCanUSB) allows, so it was not written.
+    #[error(transparent)]
+    InvalidFrameLength(#[from] InvalidFrameLength),
+    /// The frame has contradictory flags, so it was not written.
+    #[error(transparent)]
+    InvalidFrame(#[from] FrameValidationError),
+    /// Fewer responses than expected arrived before the timeout.
+    #[error("Received {} of {expected} responses before timing out", responses.len())]
+    Incomplete {
+        /// The responses that were received, in order.
+        responses: Vec<CanFdFrame>,
+        expected: usize,
+    },
+    /// The configuration value is not supported by the FdCanUSB, so it was not written.
+    #[error("Unsupported value {value} for {key}")]
+    UnsupportedConfig { key: String, value: String },
+    /// A configuration value read back after it was set differs from the value written.
+    #[error("{key} is {received} after setting it to {expected}")]
+    ConfigMismatch {
+        key: String,
+        expected: String,
+        received: String,
+    },
+    /// A response frame was expected, but no `rcv` line arrived before the timeout.
+    ///
+    /// Unlike a [`ReadError::Timeout`], no part of a response was read, so the device may never have replied.
+    #[error("No response received before timing out: {source}")]
+    NoResponse { source: std::io::Error },
+    #[error("Transfer failed at frame {index}: {source}")]
+    Frame {
+        /// The index of the frame that failed, in the order the frames were given.
+        index: usize,
+        source: Box<TransferError>,
+    },
+}
+
+#[cfg(feature = "std")]
+impl TransferError {
+    /// Returns `true` if the error is a timeout or lost sync, which usually clears if the transfer is retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TransferError::Write(WriteError::Timeout(_)) | TransferError::NoResponse { .. } => true,
+            TransferError::Read(ReadError::Timeout(_) | ReadError::LostSync { .. }) => true,
+            TransferError::Frame { source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the error was caused by a timeout, including [`TransferError::Incomplete`] and [`TransferError::NoResponse`].
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            TransferError::Write(e) => e.is_timeout(),
+            TransferError::Read(e) => e.is_timeout(),
+            TransferError::Incomplete { .. } | TransferError::NoResponse { .. } => true,
+            TransferError::Frame { source, .. } => source.is_timeout(),
+            TransferError::InvalidFrameLength(_)
+            | TransferError::InvalidFrame(_)
+            | TransferError::UnsupportedConfig { .. }
+            | TransferError::ConfigMismatch { .. } => false,
+        }
+    }
+
+    /// Returns `true` if the error was caused by the port being disconnected, e.g. the FdCanUSB was unplugged.
+    ///
+    /// For a serial port, [`FdCanUSB::reconnect`](crate::FdCanUSB::reconnect) can be used to recover.
+    pub fn is_disconnected(&self) -> bool {
+        match self {
+            TransferError::Write(e) => e.is_disconnected(),
+            TransferError::Read(e) => e.is_disconnected(),
+            TransferError::Frame { source, .. } => source.is_disconnected(),
+            TransferError::Incomplete { .. }
+            | TransferError::NoResponse { .. }
+            | TransferError::InvalidFrameLength(_)
+            | TransferError::InvalidFrame(_)
+            | TransferError::UnsupportedConfig { .. }
+            | TransferError::ConfigMismatch { .. } => false,
+        }
+    }
 }
 
+/// Returns `true` if `e` is caused by the port being disconnected.
+#[cfg(feature = "std")]
+fn is_disconnected(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::NotConnected
+    )
+}
+
+#[cfg(feature = "std")]
 #[derive(Error, Debug)]
 pub enum WriteError {
     #[error("Failed to write to port: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[source] std::io::Error),
+    /// The write timed out, so the frame may not have been sent.
+    #[error("Timed out writing to port: {0}")]
+    Timeout(#[source] std::io::Error),
+}
+
+#[cfg(feature = "std")]
+impl WriteError {
+    /// Returns `true` if the write timed out.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            WriteError::Timeout(_) => true,
+            WriteError::Io(e) => e.kind() == std::io::ErrorKind::TimedOut,
+        }
+    }
+
+    /// Returns `true` if the write failed because the port is disconnected.
+    pub fn is_disconnected(&self) -> bool {
+        match self {
+            WriteError::Io(e) => is_disconnected(e),
+            WriteError::Timeout(_) => false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for WriteError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut => WriteError::Timeout(e),
+            _ => WriteError::Io(e),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
 #[derive(Error, Debug)]
 pub enum ReadError {
     #[error("Failed to read from port: {0}")]
-    Io(#[from] std::io::Error),
+    Io(#[source] std::io::Error),
+    /// The read timed out, so the response may not have been sent.
+    #[error("Timed out reading from port: {0}")]
+    Timeout(#[source] std::io::Error),
+    /// Not returned by reads, which parse packets as bytes, see [`FdCanUSB::read`](crate::FdCanUSB::read).
+    #[deprecated(note = "reads never return this, as they parse packets as bytes")]
     #[error("Failed to parse packet into Uft8: {0}")]
     Uft8(#[from] std::str::Utf8Error),
     #[error("Failed to parse response: {0}")]
     Parse(#[from] ParseError),
+    /// A packet was longer than the maximum line length, see [`FdCanUSB::set_max_line_len`](crate::FdCanUSB::set_max_line_len).
+    #[error("Packet exceeded the maximum line length of {max} bytes")]
+    LineTooLong { max: usize },
+    /// An unexpected packet was read, see [`FdCanUSB::recent_bytes`](crate::FdCanUSB::recent_bytes) for the bytes read before it.
     #[error("Lost sync: expected {expected}, received {received}")]
     LostSync { expected: String, received: String },
+    #[error("Device responded with an error: {message}")]
+    DeviceError {
+        message: String,
+        /// The command that was rejected, if known.
+        command: Option<String>,
+    },
+}
+
+#[cfg(feature = "std")]
+impl ReadError {
+    /// Returns `true` if the read timed out.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ReadError::Timeout(_) => true,
+            ReadError::Io(e) => e.kind() == std::io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the read failed because the port is disconnected.
+    pub fn is_disconnected(&self) -> bool {
+        match self {
+            ReadError::Io(e) => is_disconnected(e),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ReadError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::TimedOut => ReadError::Timeout(e),
+            _ => ReadError::Io(e),
+        }
+    }
+}
+
+/// A read from one device of an [`FdCanUSBBus`](crate::FdCanUSBBus) failed.
+#[cfg(feature = "std")]
+#[derive(Error, Debug)]
+#[error("Failed to read from segment {segment:?}: {source}")]
+pub struct SegmentError<K: core::fmt::Debug> {
+    /// The segment of the device that failed.
+    pub segment: K,
+    pub source: ReadError,
 }
 
 #[derive(Error, Debug)]
 pub enum ParseError {
-    #[error("Unexpected data {received}, expected {expected}")]
-    UnexpectedData { expected: String, received: String },
-    #[error("Unexpected EOL, expected {expected}")]
-    UnexpectedEOL { expected: String },
-    #[error("Unexpected data with flag {flag}, {data}")]
-    UnexpectedFlagData { flag: String, data: String },
+    #[error("Unexpected data {received}, expected {expected}{}", At(.offset))]
+    UnexpectedData {
+        expected: String,
+        received: String,
+        /// The byte offset of the data in the line, if known.
+        offset: Option<usize>,
+    },
+    #[error("Unexpected EOL, expected {expected}{}", At(.offset))]
+    UnexpectedEOL {
+        expected: String,
+        /// The byte offset of the end of the line, if known.
+        offset: Option<usize>,
+    },
+    #[error("Unexpected data with flag {flag}, {data} at byte {offset}")]
+    UnexpectedFlagData {
+        flag: String,
+        data: String,
+        /// The byte offset of the flag in the line.
+        offset: usize,
+    },
     #[error("Failed to parse ID: {0}")]
-    ID(std::num::ParseIntError),
+    ID(core::num::ParseIntError),
+    /// A token is not valid hex, e.g. if bytes were lost or corrupted.
+    #[error("Malformed hex in {field} at byte {offset}: {value:?}")]
+    MalformedHex {
+        field: String,
+        value: String,
+        /// The byte offset of the field in the line.
+        offset: usize,
+    },
+    /// The data length is not a valid DLC, e.g. if bytes were lost by a partial read.
+    #[error("Invalid data length {len} at byte {offset}, not a valid DLC")]
+    InvalidDlc {
+        len: usize,
+        /// The byte offset of the data in the line.
+        offset: usize,
+    },
+    #[error(transparent)]
+    InvalidId(#[from] InvalidCanId),
     #[error("Failed to parse data: {0}")]
-    Data(#[from] hex::FromHexError),
+    // `hex::FromHexError` only implements `Error` with `std`
+    Data(#[cfg_attr(feature = "std", from)] hex::FromHexError),
     #[error("Failed to parse timestamp: {0}")]
-    TimeStamp(std::num::ParseIntError),
+    TimeStamp(core::num::ParseIntError),
+    /// A line of a log is not a valid frame, see [`parse_log`](crate::parse_log).
+    #[error("Line {line}: {source}")]
+    Line {
+        /// The line number in the log, starting at 1.
+        line: usize,
+        source: Box<ParseError>,
+    },
+    /// A log could not be read, see [`parse_log`](crate::parse_log).
+    #[cfg(feature = "std")]
+    #[error("Failed to read line {line}: {source}")]
+    Io {
+        /// The line number in the log, starting at 1.
+        line: usize,
+        source: std::io::Error,
+    },
+}
+
+/// An error reading a register of a moteus controller, see [`FdCanUSB::read_register`](crate::FdCanUSB::read_register).
+#[cfg(feature = "moteus")]
+#[derive(Error, Debug)]
+pub enum MoteusError {
+    #[error(transparent)]
+    Transfer(#[from] TransferError),
+    /// The reply has no value for the register.
+    #[error("Register {0:#05x} missing from reply")]
+    MissingRegister(u16),
+    /// The moteus reported an error reading or writing the register.
+    #[error("Error {error} for register {register:#05x}")]
+    Register { register: u16, error: u32 },
+    /// A subframe of the reply is truncated or has an unknown command.
+    #[error("Malformed reply subframe at byte {offset}")]
+    Malformed {
+        /// The byte offset of the subframe in the data.
+        offset: usize,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error(transparent)]
+    InvalidFrameLength(#[from] InvalidFrameLength),
+    #[error(transparent)]
+    InvalidDlc(#[from] InvalidDlc),
+    /// The arbitration id does not fit in 11 bits for a standard id, or 29 bits for an extended id.
+    #[error(transparent)]
+    InvalidId(#[from] InvalidCanId),
+    #[error("Buffer too small to encode frame: {required} bytes required, {available} available")]
+    BufferTooSmall { required: usize, available: usize },
+}
+
+/// Displays the byte offset of a [`ParseError`], if it is known.
+struct At<'a>(&'a Option<usize>);
+
+impl core::fmt::Display for At<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(offset) => write!(f, " at byte {offset}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An error building a frame with [`CanFdFrameBuilder`](crate::CanFdFrameBuilder).
+#[derive(Error, Debug)]
+pub enum BuildError {
+    #[error("Frame data was not set")]
+    MissingData,
+    #[error("Frame flag {0} was not set")]
+    MissingFlag(&'static str),
+    #[error("Conflicting frame flags: {0}")]
+    ConflictingFlags(&'static str),
+    #[error(transparent)]
+    InvalidFrameLength(#[from] InvalidFrameLength),
+}
+
+/// A frame with contradictory flags, see [`CanFdFrame::validate`](crate::CanFdFrame::validate).
+#[derive(Error, Debug)]
+pub enum FrameValidationError {
+    /// A remote frame has data other than zero bytes, which are only used for its DLC.
+    #[error("Remote frames can not carry data")]
+    RemoteWithData,
+    #[error("CAN FD frames can not be remote frames")]
+    RemoteFd,
+    #[error("Bit rate switching requires a CAN FD frame")]
+    BrsWithoutFd,
+    /// A classic frame has more than 8 bytes of data.
+    #[error(transparent)]
+    InvalidFrameLength(#[from] InvalidFrameLength),
+    #[error(transparent)]
+    InvalidDlc(#[from] InvalidDlc),
+    /// The arbitration id does not fit in 11 bits for a standard id, or 29 bits for an extended id.
+    #[error(transparent)]
+    InvalidId(#[from] InvalidCanId),
+}
+
+#[cfg(not(feature = "std"))]
+impl From<hex::FromHexError> for ParseError {
+    fn from(e: hex::FromHexError) -> Self {
+        ParseError::Data(e)
+    }
 }
 
 #[derive(Error, Debug)]
-#[error("Max frame length of 64 exceeded: {0}")]
-pub struct InvalidFrameLength(pub usize);
+#[error("Max frame length of {max} exceeded: {len}")]
+pub struct InvalidFrameLength {
+    /// The length of the data.
+    pub len: usize,
+    /// The maximum length allowed for the frame.
+    pub max: usize,
+}
+
+/// A [`CanFdFrame::with_dlc`](crate::CanFdFrame::with_dlc) override which is not a valid data length for the frame.
+#[derive(Error, Debug)]
+#[error("Invalid DLC override of {dlc} bytes for {len} bytes of data")]
+pub struct InvalidDlc {
+    /// The data length override.
+    pub dlc: u8,
+    /// The length of the data.
+    pub len: usize,
+}
+
+#[derive(Error, Debug)]
+#[error("Id {0:#X} is larger than 29 bits")]
+pub struct InvalidCanId(pub u32);
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn test_error_predicates() {
+        let timeout = TransferError::from(ReadError::from(Error::from(ErrorKind::TimedOut)));
+        assert!(timeout.is_timeout() && !timeout.is_disconnected());
+        let timeout = TransferError::Frame {
+            index: 1,
+            source: Box::new(WriteError::from(Error::from(ErrorKind::TimedOut)).into()),
+        };
+        assert!(timeout.is_timeout());
+        assert!(ReadError::Io(Error::from(ErrorKind::TimedOut)).is_timeout());
+        let no_response = TransferError::NoResponse {
+            source: Error::from(ErrorKind::TimedOut),
+        };
+        assert!(no_response.is_timeout() && no_response.is_retryable());
+
+        let disconnected =
+            TransferError::from(WriteError::from(Error::from(ErrorKind::BrokenPipe)));
+        assert!(disconnected.is_disconnected() && !disconnected.is_timeout());
+        assert!(ReadError::from(Error::from(ErrorKind::NotConnected)).is_disconnected());
+
+        let lost_sync = TransferError::from(ReadError::LostSync {
+            expected: "OK".to_string(),
+            received: "rcv".to_string(),
+        });
+        assert!(!lost_sync.is_timeout() && !lost_sync.is_disconnected());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::frames::CanFdFrame;
+use crate::id::CanId;
+
+bitflags::bitflags! {
+    /// The flags of a [`CanFdFrame`], as bits of a [`FrameFlags`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct FrameFlag: u8 {
+        /// The arbitration id is extended (`E`).
+        const EXTENDED = 1 << 0;
+        /// The frame uses bit rate switching (`B`).
+        const BRS = 1 << 1;
+        /// The frame is a CAN FD frame (`F`).
+        const FD = 1 << 2;
+        /// The frame is a remote frame (`R`).
+        const REMOTE = 1 << 3;
+    }
+}
+
+/// The flags of a [`CanFdFrame`] as one value, see [`CanFdFrame::flags`].
+///
+/// Each flag is either set, cleared, or left to the FdCanUSB's default,
+/// like the `Option<bool>` fields of the frame, so the flags which are set are kept with a mask of the flags which are present.
+///
+/// ```
+/// # use fdcanusb::{CanFdFrame, FrameFlag, FrameFlags};
+/// let frame = CanFdFrame::new(0x8001, &[1]).unwrap().with_brs(true);
+/// let flags = frame.flags();
+/// assert_eq!(flags.get(FrameFlag::BRS), Some(true));
+/// assert_eq!(flags.get(FrameFlag::FD), None);
+/// assert_eq!(flags.with(FrameFlag::FD, true).set(), FrameFlag::EXTENDED | FrameFlag::BRS | FrameFlag::FD);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FrameFlags {
+    set: FrameFlag,
+    present: FrameFlag,
+}
+
+impl FrameFlags {
+    /// Flags with each of `set` set, each of `cleared` cleared, and the others left to the default.
+    ///
+    /// A flag in both `set` and `cleared` is set.
+    pub fn new(set: FrameFlag, cleared: FrameFlag) -> Self {
+        FrameFlags {
+            set,
+            present: set | cleared,
+        }
+    }
+
+    /// The flags which are set.
+    pub fn set(&self) -> FrameFlag {
+        self.set
+    }
+
+    /// The flags which are either set or cleared, rather than left to the default.
+    pub fn present(&self) -> FrameFlag {
+        self.present
+    }
+
+    /// Whether `flag` is set, or `None` if it is left to the default.
+    ///
+    /// If `flag` has more than one bit, it is `Some` only if they are all present, and `Some(true)` only if they are all set.
+    pub fn get(&self, flag: FrameFlag) -> Option<bool> {
+        self.present.contains(flag).then(|| self.set.contains(flag))
+    }
+
+    /// Set `flag` to `value`, or leave it to the default if `value` is `None`.
+    pub fn insert(&mut self, flag: FrameFlag, value: Option<bool>) {
+        self.set.set(flag, value == Some(true));
+        self.present.set(flag, value.is_some());
+    }
+
+    /// These flags, with `flag` set to `value`.
+    pub fn with(mut self, flag: FrameFlag, value: bool) -> Self {
+        self.insert(flag, Some(value));
+        self
+    }
+}
+
+impl CanFdFrame {
+    /// The flags of the frame as one value.
+    ///
+    /// [`FrameFlag::EXTENDED`] is always present, from the arbitration id.
+    pub fn flags(&self) -> FrameFlags {
+        let mut flags = FrameFlags::default();
+        flags.insert(FrameFlag::EXTENDED, Some(self.arbitration_id.is_extended()));
+        flags.insert(FrameFlag::BRS, self.brs);
+        flags.insert(FrameFlag::FD, self.fd_can_frame);
+        flags.insert(FrameFlag::REMOTE, self.remote_frame);
+        flags
+    }
+
+    /// Set the flags of the frame from one value, see [`CanFdFrame::flags`].
+    ///
+    /// If [`FrameFlag::EXTENDED`] is present, the arbitration id is converted to an extended or standard id.
+    /// Ids that do not fit in 11 bits are always extended, like frames read from the FdCanUSB.
+    pub fn set_flags(&mut self, flags: FrameFlags) {
+        if let Some(extended) = flags.get(FrameFlag::EXTENDED) {
+            let raw = self.arbitration_id.as_raw();
+            self.arbitration_id = match u16::try_from(raw).ok().and_then(CanId::standard) {
+                Some(standard) if !extended => standard,
+                _ => CanId::Extended(raw),
+            };
+        }
+        self.brs = flags.get(FrameFlag::BRS);
+        self.fd_can_frame = flags.get(FrameFlag::FD);
+        self.remote_frame = flags.get(FrameFlag::REMOTE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_round_trip() {
+        let frame = CanFdFrame::new(0x8001, &[1])
+            .unwrap()
+            .with_brs(true)
+            .with_fd(true)
+            .with_remote(false);
+        let flags = frame.flags();
+        assert_eq!(
+            flags.set(),
+            FrameFlag::EXTENDED | FrameFlag::BRS | FrameFlag::FD
+        );
+        assert_eq!(flags.present(), FrameFlag::all());
+        assert_eq!(flags.get(FrameFlag::REMOTE), Some(false));
+
+        let mut copy = CanFdFrame::new(0x8001, &[1]).unwrap();
+        copy.set_flags(flags);
+        assert_eq!(copy, frame);
+
+        let mut flags = flags;
+        flags.insert(FrameFlag::BRS, None);
+        copy.set_flags(flags);
+        assert_eq!(copy.brs, None);
+    }
+
+    #[test]
+    fn test_set_extended_flag() {
+        let flags = FrameFlags::new(FrameFlag::empty(), FrameFlag::EXTENDED);
+        let mut frame = CanFdFrame::new(0x123u16, &[]).unwrap();
+        frame.set_flags(flags);
+        assert_eq!(frame.arbitration_id, CanId::Standard(0x123));
+        // Too large for a standard id
+        let mut frame = CanFdFrame::new(0x8001u16, &[]).unwrap();
+        frame.set_flags(flags);
+        assert_eq!(frame.arbitration_id, CanId::Extended(0x8001));
+        frame.set_flags(FrameFlags::default().with(FrameFlag::EXTENDED, true));
+        assert_eq!(frame.arbitration_id, CanId::Extended(0x8001));
+    }
+}
@@ -0,0 +1,256 @@
+use std::io::{Read, Write};
+
+/// Marks the start of each framed packet, so the reader can find the next packet after bytes are lost.
+const MAGIC: u8 = 0xA5;
+
+/// The length of the magic byte and the `u16` payload length.
+const HEADER_LEN: usize = 3;
+
+/// The length of the CRC-32 following the payload.
+const CRC_LEN: usize = 4;
+
+/// The longest payload accepted, well above any FdCanUSB line,
+/// so a corrupt length can not stall reads waiting for a packet that will never arrive.
+const MAX_PAYLOAD_LEN: usize = 512;
+
+/// A transport wrapper that adds a length prefix and CRC-32 to each line, for noisy links such as a TCP bridge.
+///
+/// Every line written (up to and including the `\n`) is sent as `0xA5`, the little-endian `u16` length of the line,
+/// the line itself, and the little-endian CRC-32 of the line.
+/// Reads return the lines of packets with a valid CRC, so a [`FdCanUSB`](crate::FdCanUSB) can use it like any other transport.
+///
+/// This is **not** understood by the FdCanUSB itself, and only works when both ends of the link use a `FramedTransport`,
+/// e.g. a bridge which forwards the lines it reads to the serial port, and the lines from the serial port back.
+///
+/// Lines longer than 512 bytes can not be framed.
+/// A packet with an invalid length or CRC is discarded and the read returns an [`InvalidData`](std::io::ErrorKind::InvalidData) error,
+/// after which reading resumes at the next packet.
+/// Enable with the `framed` feature.
+#[derive(Debug)]
+pub struct FramedTransport<T> {
+    inner: T,
+    /// Bytes written that do not yet end in a newline.
+    tx: Vec<u8>,
+    /// Bytes read from the inner transport that are not yet a full packet.
+    rx: Vec<u8>,
+    /// Checked payload bytes, not yet returned by `read`.
+    decoded: Vec<u8>,
+}
+
+impl<T> FramedTransport<T> {
+    /// Wrap `inner`, which must be connected to another `FramedTransport`.
+    pub fn new(inner: T) -> Self {
+        FramedTransport {
+            inner,
+            tx: Vec::new(),
+            rx: Vec::new(),
+            decoded: Vec::new(),
+        }
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume the `FramedTransport`, returning the underlying transport.
+    ///
+    /// Any buffered data is lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Take the next packet from `rx`, if a full packet has been read.
+    fn take_packet(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        match self.rx.iter().position(|&b| b == MAGIC) {
+            Some(start) => {
+                if start > 0 {
+                    debug!("framed: skipped {} bytes before packet", start);
+                }
+                self.rx.drain(..start);
+            }
+            None => {
+                self.rx.clear();
+                return Ok(None);
+            }
+        }
+        if self.rx.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = usize::from(u16::from_le_bytes([self.rx[1], self.rx[2]]));
+        if len > MAX_PAYLOAD_LEN {
+            // As for an invalid CRC, only the magic byte is discarded, and the search resumes after it.
+            self.rx.remove(0);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "framed packet has an invalid length",
+            ));
+        }
+        if self.rx.len() < HEADER_LEN + len + CRC_LEN {
+            return Ok(None);
+        }
+        let payload = &self.rx[HEADER_LEN..HEADER_LEN + len];
+        let crc = &self.rx[HEADER_LEN + len..HEADER_LEN + len + CRC_LEN];
+        if crc32(payload).to_le_bytes() != crc {
+            // The length may be corrupt too, so only the magic byte is discarded, and the search resumes after it.
+            self.rx.remove(0);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "framed packet has an invalid CRC",
+            ));
+        }
+        let payload = payload.to_vec();
+        self.rx.drain(..HEADER_LEN + len + CRC_LEN);
+        Ok(Some(payload))
+    }
+}
+
+impl<T: Write> FramedTransport<T> {
+    /// Write `payload` as a single packet.
+    fn write_packet(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        let len = u16::try_from(payload.len())
+            .ok()
+            .filter(|&len| usize::from(len) <= MAX_PAYLOAD_LEN)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "line is too long for a framed packet",
+                )
+            })?;
+        let mut packet = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+        packet.push(MAGIC);
+        packet.extend_from_slice(&len.to_le_bytes());
+        packet.extend_from_slice(payload);
+        packet.extend_from_slice(&crc32(payload).to_le_bytes());
+        self.inner.write_all(&packet)
+    }
+}
+
+impl<T: Read> Read for FramedTransport<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.decoded.is_empty() {
+            if let Some(payload) = self.take_packet()? {
+                self.decoded = payload;
+                continue;
+            }
+            let mut chunk = [0; 256];
+            let read_num = self.inner.read(&mut chunk)?;
+            if read_num == 0 {
+                return Ok(0);
+            }
+            self.rx.extend_from_slice(&chunk[..read_num]);
+        }
+        let len = buf.len().min(self.decoded.len());
+        buf[..len].copy_from_slice(&self.decoded[..len]);
+        self.decoded.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<T: Write> Write for FramedTransport<T> {
+    /// Buffers `buf`, and writes a packet for each complete line.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.tx.extend_from_slice(buf);
+        while let Some(end) = self.tx.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.tx.drain(..=end).collect();
+            self.write_packet(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes any partial line as a packet, then flushes the underlying transport.
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.tx.is_empty() {
+            let line = std::mem::take(&mut self.tx);
+            self.write_packet(&line)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// The CRC-32 (IEEE) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_framed_round_trip() {
+        let mut writer = FramedTransport::new(Vec::new());
+        writer.write_all(b"rcv 0100 01\r\nOK").unwrap();
+        writer.write_all(b"\r\n").unwrap();
+        let mut wire = writer.into_inner();
+
+        // Bytes lost before the first packet are skipped.
+        wire.insert(0, b'x');
+        let mut reader = FramedTransport::new(std::io::Cursor::new(wire));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"rcv 0100 01\r\nOK\r\n");
+    }
+
+    #[test]
+    fn test_framed_invalid_crc() {
+        let mut writer = FramedTransport::new(Vec::new());
+        writer.write_all(b"rcv 0100 01\r\nOK\r\n").unwrap();
+        let mut wire = writer.into_inner();
+        wire[HEADER_LEN] = b'R';
+
+        let mut reader = FramedTransport::new(std::io::Cursor::new(wire));
+        let mut buf = [0; 64];
+        let error = reader.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let len = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"OK\r\n");
+    }
+
+    #[test]
+    fn test_framed_invalid_length() {
+        let mut writer = FramedTransport::new(Vec::new());
+        writer.write_all(b"rcv 0100 01\r\nOK\r\n").unwrap();
+        let mut wire = writer.into_inner();
+        wire[1..3].copy_from_slice(&u16::MAX.to_le_bytes());
+
+        // The corrupt length is rejected without waiting for 65535 bytes, and the next packet is still read.
+        let mut reader = FramedTransport::new(std::io::Cursor::new(wire));
+        let mut buf = [0; 64];
+        let error = reader.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let len = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"OK\r\n");
+    }
+
+    #[test]
+    fn test_framed_line_too_long() {
+        let mut writer = FramedTransport::new(Vec::new());
+        let error = writer
+            .write_all(&[b'0'; MAX_PAYLOAD_LEN + 1])
+            .and_then(|()| writer.flush())
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
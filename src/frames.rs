@@ -1,16 +1,30 @@
-use crate::error::{InvalidFrameLength, ParseError};
+use alloc::borrow::ToOwned;
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::error::{
+    BuildError, EncodeError, FrameValidationError, InvalidCanId, InvalidDlc, InvalidFrameLength,
+    ParseError,
+};
+use crate::id::CanId;
+
+/// The maximum data length of a CAN FD frame.
+pub(crate) const MAX_FD_DATA_LEN: usize = 64;
+/// The maximum data length of a classic CAN frame.
+const MAX_CLASSIC_DATA_LEN: usize = 8;
 
 /// `CanFdFrame` represents a single frame of data on the CAN bus.
-#[derive(Debug, Default)]
+///
+/// Frames are ordered by arbitration id, then data, then flags and timestamp, so they can be kept in a `BTreeSet`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct CanFdFrame {
-    /// The arbitration id of the frame.
-    ///
-    // TODO: Integrate with the `CanId` type from the `socketcan` crate? or reimplement it?
-    pub arbitration_id: u16,
+    /// The arbitration id of the frame, which is either standard or extended.
+    pub arbitration_id: CanId,
     /// The data of the frame, up to 64 bytes.
     pub data: Vec<u8>,
-    /// If the frame has an extended id.
-    pub extended_id: Option<bool>,
     /// If the frame has bit rate switching.
     pub brs: Option<bool>,
     /// If the frame is a CAN FD frame.
@@ -19,48 +33,638 @@ pub struct CanFdFrame {
     pub remote_frame: Option<bool>,
     /// The timestamp of the frame.
     pub timestamp: Option<u32>,
+    /// The data length to send, overriding the length the data is padded to, see [`CanFdFrame::with_dlc`].
+    pub dlc: Option<u8>,
 }
 
 impl CanFdFrame {
+    /// The maximum number of bytes [`CanFdFrame::encode_into`] can write.
+    pub const MAX_ENCODED_LEN: usize = 155;
+
     /// Create a new `CanFdFrame` with the given arbitration id and data.
     /// returns an `Err` if the length of `data` is > 64
     ///
+    /// `data` may be empty, in which case the frame is sent with an empty data field, e.g. `can send 8001  E\n`.
+    ///
     /// Use [`CanFdFrame::new_with_flags`] to set the flags.
-    pub fn new(arbitration_id: u16, data: &[u8]) -> Result<CanFdFrame, InvalidFrameLength> {
-        if data.len() > 64 {
-            return Err(InvalidFrameLength(data.len()));
-        }
+    pub fn new(
+        arbitration_id: impl Into<CanId>,
+        data: &[u8],
+    ) -> Result<CanFdFrame, InvalidFrameLength> {
+        check_data_len(data, MAX_FD_DATA_LEN)?;
         Ok(CanFdFrame {
-            arbitration_id,
+            arbitration_id: arbitration_id.into(),
+            data: data.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    /// Create a new classic CAN `CanFdFrame` with the given arbitration id and data.
+    /// returns an `Err` if the length of `data` is > 8
+    ///
+    /// The frame is sent with the `f` flag, and the data is not padded.
+    pub fn new_classic(
+        arbitration_id: impl Into<CanId>,
+        data: &[u8],
+    ) -> Result<CanFdFrame, InvalidFrameLength> {
+        check_data_len(data, MAX_CLASSIC_DATA_LEN)?;
+        Ok(CanFdFrame {
+            arbitration_id: arbitration_id.into(),
             data: data.to_owned(),
+            fd_can_frame: Some(false),
             ..Default::default()
         })
     }
 
+    /// Create a new remote transmission request `CanFdFrame`, requesting `dlc` bytes of data.
+    /// returns an `Err` if `dlc` is > 8, as remote frames are classic CAN frames.
+    ///
+    /// The FdCanUSB takes the DLC of a remote frame from the length of its data,
+    /// so the frame has `dlc` zero bytes of data, and is sent with the `f` and `R` flags,
+    /// e.g. `can send 8001 0000000000000000 E f R`. Use [`CanFdFrame::dlc`] to read the DLC back.
+    pub fn new_remote(
+        arbitration_id: impl Into<CanId>,
+        dlc: usize,
+    ) -> Result<CanFdFrame, InvalidFrameLength> {
+        let data = [0; MAX_CLASSIC_DATA_LEN];
+        let data = data.get(..dlc).ok_or(InvalidFrameLength {
+            len: dlc,
+            max: MAX_CLASSIC_DATA_LEN,
+        })?;
+        Ok(CanFdFrame::new_classic(arbitration_id, data)?.with_remote(true))
+    }
+
     /// Create a new `CanFdFrame` with the given arbitration id, data and flags.
-    /// returns an `Err` if the length of `data` is > 64
+    /// returns an `Err` if the length of `data` is > 64, or > 8 if `fd_can_frame` is `Some(false)`
     pub fn new_with_flags(
-        arbitration_id: u16,
+        arbitration_id: impl Into<CanId>,
         data: &[u8],
-        extended_id: Option<bool>,
         brs: Option<bool>,
         fd_can_frame: Option<bool>,
         remote_frame: Option<bool>,
         timestamp: Option<u32>,
     ) -> Result<CanFdFrame, InvalidFrameLength> {
-        if data.len() > 64 {
-            return Err(InvalidFrameLength(data.len()));
+        match fd_can_frame {
+            Some(false) => check_data_len(data, MAX_CLASSIC_DATA_LEN)?,
+            _ => check_data_len(data, MAX_FD_DATA_LEN)?,
         }
         Ok(CanFdFrame {
-            arbitration_id,
+            arbitration_id: arbitration_id.into(),
             data: data.to_owned(),
-            extended_id,
             brs,
             fd_can_frame,
             remote_frame,
             timestamp,
+            dlc: None,
         })
     }
+
+    /// Create a [`CanFdFrameBuilder`], which requires the data and every flag to be set before the frame is built.
+    ///
+    /// ```
+    /// # use fdcanusb::CanFdFrame;
+    /// let frame = CanFdFrame::builder(0x8001)
+    ///     .data(&[1, 2, 3])
+    ///     .fd(true)
+    ///     .brs(true)
+    ///     .remote(false)
+    ///     .build()?;
+    /// # Ok::<(), fdcanusb::BuildError>(())
+    /// ```
+    pub fn builder(arbitration_id: impl Into<CanId>) -> CanFdFrameBuilder {
+        CanFdFrameBuilder {
+            arbitration_id: arbitration_id.into(),
+            data: None,
+            brs: None,
+            fd_can_frame: None,
+            remote_frame: None,
+        }
+    }
+
+    /// Set whether the frame uses bit rate switching.
+    pub fn with_brs(mut self, brs: bool) -> CanFdFrame {
+        self.brs = Some(brs);
+        self
+    }
+
+    /// Set whether the frame is a CAN FD frame.
+    ///
+    /// Classic frames are limited to 8 bytes, which is checked when the frame is encoded.
+    pub fn with_fd(mut self, fd_can_frame: bool) -> CanFdFrame {
+        self.fd_can_frame = Some(fd_can_frame);
+        self
+    }
+
+    /// Set whether the frame is a remote frame.
+    pub fn with_remote(mut self, remote_frame: bool) -> CanFdFrame {
+        self.remote_frame = Some(remote_frame);
+        self
+    }
+
+    /// Set the timestamp of the frame.
+    pub fn with_timestamp(mut self, timestamp: u32) -> CanFdFrame {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Set the data length to send, overriding the length the data is padded to.
+    ///
+    /// The FdCanUSB takes the DLC from the number of data bytes sent, so the data is padded to `dlc` bytes when encoded.
+    /// `dlc` must be a valid data length, see [`is_valid_dlc`], no shorter than the data, and at most 8 for a classic frame,
+    /// which is checked when the frame is encoded.
+    pub fn with_dlc(mut self, dlc: u8) -> CanFdFrame {
+        self.dlc = Some(dlc);
+        self
+    }
+
+    /// The data length code of the frame, which is the length of its data, or the [`CanFdFrame::with_dlc`] override.
+    ///
+    /// For a remote frame, this is the number of bytes requested, see [`CanFdFrame::new_remote`].
+    pub fn dlc(&self) -> usize {
+        self.dlc.map_or(self.data.len(), usize::from)
+    }
+
+    /// The raw arbitration id and data of the frame, e.g. for a signal decoder.
+    ///
+    /// The id is the same for a standard and an extended id with the same value,
+    /// so use [`CanId::to_dbc_id`] for the id of a DBC file, which marks extended ids.
+    /// A frame can be created from a decoded message with [`CanId::from_dbc_id`] and [`CanFdFrame::new`].
+    ///
+    /// ```
+    /// # use fdcanusb::{CanFdFrame, CanId};
+    /// let frame = CanFdFrame::new(CanId::from_dbc_id(0x8000_8001)?, &[1, 2])?;
+    /// assert_eq!(frame.as_raw(), (0x8001, &[1, 2][..]));
+    /// assert_eq!(frame.arbitration_id.to_dbc_id(), 0x8000_8001);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn as_raw(&self) -> (u32, &[u8]) {
+        (self.arbitration_id.as_raw(), &self.data)
+    }
+
+    /// The data of the frame.
+    ///
+    /// Unlike `embedded_can::Frame::data`, this includes the `dlc` bytes sent with a remote frame.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The data of the frame, to modify in place.
+    ///
+    /// The length can not be changed through this, use [`CanFdFrame::set_data`] to resize the data.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Replace the data of the frame, keeping its arbitration id and flags.
+    /// returns an `Err` and leaves the frame unchanged if the length of `data` is > 64,
+    /// or > 8 if the frame is a classic frame.
+    pub fn set_data(&mut self, data: &[u8]) -> Result<(), InvalidFrameLength> {
+        let max = match self.fd_can_frame {
+            Some(false) => MAX_CLASSIC_DATA_LEN,
+            _ => MAX_FD_DATA_LEN,
+        };
+        check_data_len(data, max)?;
+        self.data.clear();
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Returns `true` if the frame requests a reply, using the moteus arbitration id convention.
+    ///
+    /// moteus ids are laid out as:
+    /// - bits 0-7: the destination id
+    /// - bits 8-14: the source id
+    /// - bit 15 (`0x8000`): set if a reply is requested
+    ///
+    /// A frame without the bit set gets no `rcv` frame back, so waiting for one would time out.
+    pub fn expects_response(&self) -> bool {
+        self.arbitration_id.as_raw() & 0x8000 != 0
+    }
+
+    /// The source id of the frame, bits 8-14 of the arbitration id, using the moteus convention, see [`CanFdFrame::expects_response`].
+    ///
+    /// For a reply from a moteus controller, this is the id of the controller.
+    pub fn source_id(&self) -> u8 {
+        ((self.arbitration_id.as_raw() >> 8) & 0x7F) as u8
+    }
+
+    /// Returns `true` if the frame is from the device with the given `id`, see [`CanFdFrame::source_id`].
+    ///
+    /// This tells replies from different devices apart on a busy bus, e.g. with [`FdCanUSB::read_from`](crate::FdCanUSB::read_from).
+    pub fn is_from(&self, id: u16) -> bool {
+        u16::from(self.source_id()) == id
+    }
+
+    /// The timestamp of the frame, which the FdCanUSB measures in microseconds.
+    ///
+    /// The device clock wraps about every 71 minutes, and is not related to the host clock,
+    /// use [`FdCanUSB::host_timestamp`](crate::FdCanUSB::host_timestamp) for a host time.
+    pub fn timestamp_duration(&self) -> Option<core::time::Duration> {
+        self.timestamp
+            .map(|timestamp| core::time::Duration::from_micros(u64::from(timestamp)))
+    }
+
+    /// Encode the frame as a `can send` command into `out`, without allocating.
+    /// Returns the number of bytes written.
+    ///
+    /// This produces the same bytes as converting the frame into a [`FdCanUSBFrame`].
+    /// `out` must be large enough for the encoded frame, [`CanFdFrame::MAX_ENCODED_LEN`] is always enough.
+    pub fn encode_into(&self, out: &mut [u8]) -> Result<usize, EncodeError> {
+        self.encode_into_with_defaults((None, None, None), out)
+    }
+
+    /// Check the flags of the frame are consistent with each other and its data.
+    ///
+    /// Returns an `Err` if the frame is a remote frame with data, which must be zero bytes for its DLC, or a CAN FD frame,
+    /// if bit rate switching is set for a classic frame, if a classic frame has more than 8 bytes,
+    /// or if the arbitration id does not fit in its 11 or 29 bits, see [`CanId::is_valid`].
+    /// Flags which are `None` are left to the FdCanUSB, so they are not checked.
+    ///
+    /// This is checked by the [`FdCanUSB`](crate::FdCanUSB) before a frame is written, with its default flags applied.
+    pub fn validate(&self) -> Result<(), FrameValidationError> {
+        self.validate_with_defaults((None, None, None))
+    }
+
+    /// Check the frame like [`CanFdFrame::validate`],
+    /// with the `brs`, `fd_can_frame` and `remote_frame` flags it leaves as `None` set to `defaults`.
+    pub(crate) fn validate_with_defaults(
+        &self,
+        defaults: (Option<bool>, Option<bool>, Option<bool>),
+    ) -> Result<(), FrameValidationError> {
+        let brs = self.brs.or(defaults.0);
+        let fd_can_frame = self.fd_can_frame.or(defaults.1);
+        let remote_frame = self.remote_frame.or(defaults.2);
+        self.arbitration_id.check()?;
+        if remote_frame == Some(true) {
+            if self.data.iter().any(|&b| b != 0) {
+                return Err(FrameValidationError::RemoteWithData);
+            }
+            if fd_can_frame == Some(true) {
+                return Err(FrameValidationError::RemoteFd);
+            }
+        }
+        if fd_can_frame == Some(false) {
+            if brs == Some(true) {
+                return Err(FrameValidationError::BrsWithoutFd);
+            }
+            check_data_len(&self.data, MAX_CLASSIC_DATA_LEN)?;
+        }
+        if let Some(dlc) = self.dlc {
+            check_dlc(dlc, self.data.len(), fd_can_frame)?;
+        }
+        Ok(())
+    }
+
+    /// The `can send` command the frame is encoded as, without any IO.
+    ///
+    /// This is useful to check the exact bytes a frame produces, e.g. before wiring up a new controller.
+    /// Unlike converting the frame into a [`FdCanUSBFrame`], this returns an `Err` rather than panicking
+    /// if the data is too long or the [`CanFdFrame::with_dlc`] override is invalid.
+    /// The [`FdCanUSB`](crate::FdCanUSB) may also set default flags, see [`FdCanUSB::set_default_flags`](crate::FdCanUSB::set_default_flags).
+    pub fn dry_run(&self) -> Result<String, EncodeError> {
+        let mut buffer = [0; CanFdFrame::MAX_ENCODED_LEN];
+        // Every frame fits in `MAX_ENCODED_LEN` bytes, so this never returns `BufferTooSmall`
+        let len = self.encode_into(&mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer[..len]).into_owned())
+    }
+
+    /// The `can send` command for the frame as a classic CAN frame, whatever its `fd_can_frame` flag.
+    ///
+    /// With [`CanFdFrame::encode_fd`], this shows how the same data is sent in each mode, e.g. to debug a controller which
+    /// behaves differently in FD and classic mode. A classic frame is sent with the `f` flag and no bit rate switching flag,
+    /// and its data is not padded, so it returns an `Err` if the data is longer than 8 bytes, or if the id is out of range.
+    /// The [`CanFdFrame::with_dlc`] override is not used, so the data length follows the rules of the mode.
+    pub fn encode_classic(&self) -> Result<String, EncodeError> {
+        let flags = [(None, b'B'), (Some(false), b'F'), (self.remote_frame, b'R')];
+        self.encode_with_max_len(flags, MAX_CLASSIC_DATA_LEN)
+    }
+
+    /// The `can send` command for the frame as a CAN FD frame, whatever its `fd_can_frame` flag.
+    ///
+    /// A CAN FD frame is sent with the `F` flag, keeping the frame's bit rate switching flag,
+    /// and its data is padded to the next valid DLC, up to 64 bytes. See [`CanFdFrame::encode_classic`].
+    pub fn encode_fd(&self) -> Result<String, EncodeError> {
+        let flags = [
+            (self.brs, b'B'),
+            (Some(true), b'F'),
+            (self.remote_frame, b'R'),
+        ];
+        self.encode_with_max_len(flags, MAX_FD_DATA_LEN)
+    }
+
+    /// Encode the frame with `flags`, padding its data like a frame with a maximum data length of `max`,
+    /// for [`CanFdFrame::encode_classic`] and [`CanFdFrame::encode_fd`].
+    fn encode_with_max_len(&self, flags: Flags, max: usize) -> Result<String, EncodeError> {
+        self.arbitration_id.check()?;
+        let data_len = padded_len(&self.data, max)?;
+        let mut out = vec![0; self.encoded_len(&flags, data_len)];
+        self.write_encoded(
+            &flags,
+            data_len,
+            &mut SliceWriter {
+                out: &mut out,
+                pos: 0,
+            },
+        );
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    /// Encode the frame like [`CanFdFrame::encode_into`],
+    /// with the `brs`, `fd_can_frame` and `remote_frame` flags it leaves as `None` set to `defaults`.
+    pub(crate) fn encode_into_with_defaults(
+        &self,
+        defaults: (Option<bool>, Option<bool>, Option<bool>),
+        out: &mut [u8],
+    ) -> Result<usize, EncodeError> {
+        let brs = self.brs.or(defaults.0);
+        let fd_can_frame = self.fd_can_frame.or(defaults.1);
+        let remote_frame = self.remote_frame.or(defaults.2);
+        // An id out of range would be truncated to the wrong id
+        self.arbitration_id.check()?;
+        let data_len = match (fd_can_frame, self.dlc) {
+            (_, Some(dlc)) => {
+                check_dlc(dlc, self.data.len(), fd_can_frame)?;
+                usize::from(dlc)
+            }
+            (Some(false), None) => padded_len(&self.data, MAX_CLASSIC_DATA_LEN)?,
+            (_, None) => padded_len(&self.data, MAX_FD_DATA_LEN)?,
+        };
+        let flags = [(brs, b'B'), (fd_can_frame, b'F'), (remote_frame, b'R')];
+        let required = self.encoded_len(&flags, data_len);
+        if out.len() < required {
+            return Err(EncodeError::BufferTooSmall {
+                required,
+                available: out.len(),
+            });
+        }
+        let mut out = SliceWriter { out, pos: 0 };
+        self.write_encoded(&flags, data_len, &mut out);
+        Ok(out.pos)
+    }
+
+    /// The number of hex digits the arbitration id is encoded with.
+    fn id_digits(&self) -> usize {
+        match self.arbitration_id {
+            CanId::Standard(_) => 3,
+            CanId::Extended(id) => (8 - id.leading_zeros() as usize / 4).max(4),
+        }
+    }
+
+    /// The number of bytes [`CanFdFrame::write_encoded`] writes.
+    fn encoded_len(&self, flags: &Flags, data_len: usize) -> usize {
+        let flags_len = 2 * flags.iter().filter(|(flag, _)| flag.is_some()).count();
+        b"can send ".len() + self.id_digits() + 1 + 2 * data_len + 2 + flags_len + 1
+    }
+
+    /// Write the `can send` command, with the data padded to `data_len` bytes.
+    /// `out` must have room for [`CanFdFrame::encoded_len`] bytes.
+    fn write_encoded(&self, flags: &Flags, data_len: usize, out: &mut SliceWriter) {
+        // The padding of a remote frame is only its DLC, so it is zero like its data
+        let padding: &[u8] = match flags[2].0 {
+            Some(true) => b"00",
+            _ => b"50",
+        };
+        out.push(b"can send ");
+        out.push_hex_digits(self.arbitration_id.as_raw(), self.id_digits());
+        out.push(b" ");
+        out.push_hex(&self.data);
+        for _ in self.data.len()..data_len {
+            out.push(padding);
+        }
+        // The id width is also sent as a flag, so the FdCanUSB does not guess it from the number of digits
+        out.push(match self.arbitration_id {
+            CanId::Standard(_) => b" e",
+            CanId::Extended(_) => b" E",
+        });
+        for &(flag, c) in flags {
+            match flag {
+                Some(true) => out.push(&[b' ', c]),
+                Some(false) => out.push(&[b' ', c.to_ascii_lowercase()]),
+                None => {}
+            }
+        }
+        out.push(b"\n");
+    }
+}
+
+/// The `brs`, `fd_can_frame` and `remote_frame` flags of an encoded frame, with the letter each is sent as.
+type Flags = [(Option<bool>, u8); 3];
+
+/// The length `data` is padded to in a frame with a maximum data length of `max`.
+///
+/// Classic frames are not padded, as they can be any length up to 8, and CAN FD frames are padded to a valid DLC.
+fn padded_len(data: &[u8], max: usize) -> Result<usize, InvalidFrameLength> {
+    check_data_len(data, max)?;
+    if max <= MAX_CLASSIC_DATA_LEN {
+        return Ok(data.len());
+    }
+    round_up_dlc(data.len()).ok_or(InvalidFrameLength {
+        len: data.len(),
+        max,
+    })
+}
+
+/// A strict builder for a [`CanFdFrame`], created with [`CanFdFrame::builder`].
+///
+/// Unlike [`CanFdFrame::new`], no flag is left to the FdCanUSB's default,
+/// so [`CanFdFrameBuilder::build`] fails unless the data and every flag are set.
+#[derive(Debug, Clone)]
+pub struct CanFdFrameBuilder {
+    arbitration_id: CanId,
+    data: Option<Vec<u8>>,
+    brs: Option<bool>,
+    fd_can_frame: Option<bool>,
+    remote_frame: Option<bool>,
+}
+
+impl CanFdFrameBuilder {
+    /// Set the data of the frame. Classic frames are limited to 8 bytes, CAN FD frames to 64.
+    pub fn data(mut self, data: &[u8]) -> Self {
+        self.data = Some(data.to_owned());
+        self
+    }
+
+    /// Set whether the frame uses bit rate switching (`B`), which requires a CAN FD frame.
+    pub fn brs(mut self, brs: bool) -> Self {
+        self.brs = Some(brs);
+        self
+    }
+
+    /// Set whether the frame is a CAN FD frame (`F`), or a classic frame.
+    pub fn fd(mut self, fd_can_frame: bool) -> Self {
+        self.fd_can_frame = Some(fd_can_frame);
+        self
+    }
+
+    /// Set whether the frame is a remote frame (`R`), which requires a classic frame.
+    pub fn remote(mut self, remote_frame: bool) -> Self {
+        self.remote_frame = Some(remote_frame);
+        self
+    }
+
+    /// Build the frame, checking that the data and every flag are set and are consistent.
+    pub fn build(self) -> Result<CanFdFrame, BuildError> {
+        let data = self.data.ok_or(BuildError::MissingData)?;
+        let brs = self.brs.ok_or(BuildError::MissingFlag("brs"))?;
+        let fd_can_frame = self.fd_can_frame.ok_or(BuildError::MissingFlag("fd"))?;
+        let remote_frame = self.remote_frame.ok_or(BuildError::MissingFlag("remote"))?;
+        if brs && !fd_can_frame {
+            return Err(BuildError::ConflictingFlags(
+                "bit rate switching requires a CAN FD frame",
+            ));
+        }
+        if remote_frame && fd_can_frame {
+            return Err(BuildError::ConflictingFlags(
+                "CAN FD frames can not be remote frames",
+            ));
+        }
+        Ok(CanFdFrame::new_with_flags(
+            self.arbitration_id,
+            &data,
+            Some(brs),
+            Some(fd_can_frame),
+            Some(remote_frame),
+            None,
+        )?)
+    }
+}
+
+/// Uppercase hex digits, used to encode frames without allocating.
+/// (`hex::encode_to_slice` only produces lowercase hex.)
+const HEX_UPPER: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Writes bytes into a slice, which must be large enough for all of them.
+struct SliceWriter<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl SliceWriter<'_> {
+    fn push(&mut self, bytes: &[u8]) {
+        self.out[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    /// Push the lowest `digits` hex digits of `value`.
+    fn push_hex_digits(&mut self, value: u32, digits: usize) {
+        for digit in (0..digits).rev() {
+            self.push(&[HEX_UPPER[(value >> (4 * digit)) as usize & 0xF]]);
+        }
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(&[
+                HEX_UPPER[usize::from(byte >> 4)],
+                HEX_UPPER[usize::from(byte & 0xF)],
+            ]);
+        }
+    }
+}
+
+/// Returns an `Err` if `data` is longer than `max`.
+fn check_data_len(data: &[u8], max: usize) -> Result<(), InvalidFrameLength> {
+    if data.len() > max {
+        return Err(InvalidFrameLength {
+            len: data.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Split a payload larger than one frame into CAN FD frames with the same arbitration id, e.g. for [`FdCanUSB::transfer_many`](crate::FdCanUSB::transfer_many).
+///
+/// `data` is split into chunks of 64 bytes, in order, and the last frame has the rest, so an empty payload has no frames.
+/// No framing is added, so the receiver reassembles the payload by concatenating the data of the frames in the order they arrive,
+/// and must know the length of the payload, as the last frame is padded to a valid DLC when it is sent (see [`round_up_dlc`]).
+/// Protocols which need to mark the first and consecutive frames, like ISO-TP, can build on this with a chunk size which leaves room for their header.
+///
+/// ```
+/// # use fdcanusb::segment;
+/// let frames: Vec<_> = segment(0x8001, &[0; 100]).collect();
+/// assert_eq!(frames.len(), 2);
+/// assert_eq!((frames[0].data.len(), frames[1].data.len()), (64, 36));
+/// ```
+pub fn segment(
+    arbitration_id: impl Into<CanId>,
+    data: &[u8],
+) -> impl Iterator<Item = CanFdFrame> + '_ {
+    let arbitration_id = arbitration_id.into();
+    data.chunks(MAX_FD_DATA_LEN).map(move |chunk| {
+        CanFdFrame::new(arbitration_id, chunk).expect("chunks are at most 64 bytes")
+    })
+}
+
+/// Parse a log of FdCanUSB output, e.g. captured with `cat /dev/fdcanusb`, into the `rcv` frames it contains, without opening a port.
+///
+/// Lines are split at `\n`, so `\r\n` line endings are also handled.
+/// Blank lines, `OK` acknowledgments, and echoed `can send` commands are skipped.
+/// Any other line that is not a valid `rcv` frame yields a [`ParseError::Line`] with its line number,
+/// and the iterator continues with the next line, so the caller decides whether to stop,
+/// e.g. corrupt lines can be skipped with `filter_map(Result::ok)`.
+/// An error reading `reader` yields a [`ParseError::Io`], and ends the iterator.
+///
+/// ```
+/// # use fdcanusb::parse_log;
+/// let log = b"can send 8001 01\r\nOK\r\n\r\nrcv 0100 02 E B F\r\n";
+/// let frames: Vec<_> = parse_log(&log[..]).collect::<Result<_, _>>().unwrap();
+/// assert_eq!(frames[0].data, [2]);
+/// ```
+#[cfg(feature = "std")]
+pub fn parse_log(
+    reader: impl std::io::BufRead,
+) -> impl Iterator<Item = Result<CanFdFrame, ParseError>> {
+    let mut lines = reader.split(b'\n').zip(1..);
+    let mut failed = false;
+    core::iter::from_fn(move || loop {
+        if failed {
+            return None;
+        }
+        let (line, number) = lines.next()?;
+        let line = match line {
+            Ok(line) => line,
+            Err(source) => {
+                // A reader which failed may fail again forever, e.g. if the caller skips errors
+                failed = true;
+                return Some(Err(ParseError::Io {
+                    line: number,
+                    source,
+                }));
+            }
+        };
+        let line = line.trim_ascii();
+        if line.is_empty() || line.starts_with(b"OK") || line.starts_with(b"can send") {
+            continue;
+        }
+        return Some(CanFdFrame::from_bytes(line).map_err(|e| ParseError::Line {
+            line: number,
+            source: Box::new(e),
+        }));
+    })
+}
+
+/// Round `len` up to the nearest valid CAN FD data length.
+///
+/// Lengths up to 8 are returned unchanged, larger lengths are rounded up to one of 12, 16, 20, 24, 32, 48 or 64.
+/// Returns `None` if `len` is greater than 64.
+pub fn round_up_dlc(len: usize) -> Option<usize> {
+    match len {
+        ..=8 => Some(len),
+        9..=12 => Some(12),
+        13..=16 => Some(16),
+        17..=20 => Some(20),
+        21..=24 => Some(24),
+        25..=32 => Some(32),
+        33..=48 => Some(48),
+        49..=64 => Some(64),
+        _ => None,
+    }
+}
+
+/// Returns `true` if `len` is a valid CAN FD data length.
+pub fn is_valid_dlc(len: usize) -> bool {
+    round_up_dlc(len) == Some(len)
 }
 
 /// The FdCanUSB communicates over Serial using ascii encoded frames.
@@ -79,89 +683,197 @@ impl From<&str> for FdCanUSBFrame {
     }
 }
 
+impl core::str::FromStr for FdCanUSBFrame {
+    type Err = ParseError;
+    /// Create a `FdCanUSBFrame` from a string, checking that it is a valid frame.
+    ///
+    /// The string must start with a known verb (`can send`, `rcv`, `OK` or `conf`).
+    /// For `can send` and `rcv` the id and data must also be valid hex.
+    ///
+    /// `TryFrom<&str>` conflicts with the lenient `From<&str>`, so use [`str::parse`] instead.
+    fn from_str(data: &str) -> Result<FdCanUSBFrame, ParseError> {
+        let line = data.trim();
+        let offset_of = |token: &str| token.as_ptr() as usize - data.as_ptr() as usize;
+        let eol = || Some(offset_of(line) + line.len());
+        let mut iter = line.split(' ');
+        match iter.next() {
+            Some("can") => match iter.next() {
+                Some("send") => {}
+                Some(unexpected) => {
+                    return Err(ParseError::UnexpectedData {
+                        expected: "send".to_string(),
+                        received: unexpected.to_string(),
+                        offset: Some(offset_of(unexpected)),
+                    })
+                }
+                None => {
+                    return Err(ParseError::UnexpectedEOL {
+                        expected: "send".to_string(),
+                        offset: eol(),
+                    })
+                }
+            },
+            Some("rcv") => {}
+            Some("OK") | Some("conf") => return Ok(FdCanUSBFrame(data.to_owned())),
+            Some(unexpected) => {
+                return Err(ParseError::UnexpectedData {
+                    expected: "can send, rcv, OK or conf".to_string(),
+                    received: unexpected.to_string(),
+                    offset: Some(offset_of(unexpected)),
+                })
+            }
+            None => {
+                return Err(ParseError::UnexpectedEOL {
+                    expected: "can send, rcv, OK or conf".to_string(),
+                    offset: eol(),
+                })
+            }
+        }
+
+        let id = iter.next().ok_or_else(|| ParseError::UnexpectedEOL {
+            expected: "id".to_string(),
+            offset: eol(),
+        })?;
+        u32::from_str_radix(id, 16).map_err(ParseError::ID)?;
+
+        // The data may be missing for a zero-length frame
+        hex::decode(iter.next().unwrap_or_default())?;
+
+        Ok(FdCanUSBFrame(data.to_owned()))
+    }
+}
+
 impl FdCanUSBFrame {
     /// Get the ascii encoded frame as a byte slice.
     pub fn as_bytes(&self) -> &[u8] {
         self.0.as_bytes()
     }
+
+    /// Get the ascii encoded frame as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Consume the `FdCanUSBFrame`, returning the ascii encoded frame.
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl AsRef<str> for FdCanUSBFrame {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl core::fmt::Display for FdCanUSBFrame {
+    /// Writes the ascii encoded frame exactly, including any line terminator.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 // TODO: change to use `TryFrom`
 impl From<CanFdFrame> for FdCanUSBFrame {
     fn from(frame: CanFdFrame) -> FdCanUSBFrame {
-        let id = hex::encode_upper(frame.arbitration_id.to_be_bytes());
-        let num_bytes = frame.data.len();
-        let data = hex::encode_upper(frame.data);
-        let data_len = {
-            match num_bytes {
-                ..=8 => num_bytes,
-                9..=12 => 12,
-                13..=16 => 16,
-                17..=20 => 20,
-                21..=24 => 24,
-                25..=32 => 32,
-                33..=48 => 48,
-                49..=64 => 64,
-                _ => panic!("Invalid data length {num_bytes}"),
-            }
-        };
-        let padding_len = data_len - num_bytes; // data_len will always be equal or greater than num_bytes
-        let padding: String = (0..padding_len).map(|_| "50").collect();
-        let data = format!("{data}{padding}");
-        let flags = {
-            let mut flags = String::new();
-            match frame.brs {
-                Some(true) => flags.push_str(" B"),
-                Some(false) => flags.push_str(" b"),
-                None => {}
-            }
-            match frame.fd_can_frame {
-                Some(true) => flags.push_str(" F"),
-                Some(false) => flags.push_str(" f"),
-                None => {}
-            }
-            match frame.remote_frame {
-                Some(true) => flags.push_str(" R"),
-                Some(false) => flags.push_str(" r"),
-                None => {}
-            }
-            flags
-        };
-        FdCanUSBFrame(format!("can send {id} {data}{flags}\n"))
+        let mut buffer = vec![0; CanFdFrame::MAX_ENCODED_LEN];
+        let len = frame
+            .encode_into(&mut buffer)
+            .unwrap_or_else(|e| panic!("Failed to encode frame: {e}"));
+        buffer.truncate(len);
+        FdCanUSBFrame(String::from_utf8(buffer).expect("encoded frames are ascii"))
+    }
+}
+
+/// Create a frame from an arbitration id and data, inferring whether it is a CAN FD frame from the length of the data.
+///
+/// Data longer than 8 bytes is sent as a CAN FD frame, and 8 bytes or fewer as a classic frame, with the `f` flag.
+/// Returns an `Err` if the data is longer than 64 bytes. Use [`CanFdFrame::new`] to leave the flag to the FdCanUSB.
+///
+/// ```
+/// # use fdcanusb::CanFdFrame;
+/// let frame = CanFdFrame::try_from((0x8001, &[0x01, 0x02][..]))?;
+/// assert_eq!(frame.fd_can_frame, Some(false));
+/// # Ok::<(), fdcanusb::InvalidFrameLength>(())
+/// ```
+impl TryFrom<(CanId, &[u8])> for CanFdFrame {
+    type Error = InvalidFrameLength;
+    fn try_from((arbitration_id, data): (CanId, &[u8])) -> Result<Self, Self::Error> {
+        let fd_can_frame = data.len() > MAX_CLASSIC_DATA_LEN;
+        Ok(CanFdFrame::new(arbitration_id, data)?.with_fd(fd_can_frame))
+    }
+}
+
+/// Create a frame from an extended id, like [`CanId::from`], see `TryFrom<(CanId, &[u8])>`.
+impl TryFrom<(u16, &[u8])> for CanFdFrame {
+    type Error = InvalidFrameLength;
+    fn try_from((arbitration_id, data): (u16, &[u8])) -> Result<Self, Self::Error> {
+        CanFdFrame::try_from((CanId::from(arbitration_id), data))
     }
 }
 
 impl TryFrom<FdCanUSBFrame> for CanFdFrame {
     type Error = ParseError;
     fn try_from(data: FdCanUSBFrame) -> Result<Self, Self::Error> {
-        let mut iter = data.0.trim().split(' ');
+        CanFdFrame::from_bytes(data.as_bytes())
+    }
+}
+
+impl CanFdFrame {
+    /// Parse a `rcv` line from the FdCanUSB, e.g. `rcv 0100 01 E B F`.
+    ///
+    /// This is the same as [`CanFdFrame::from_bytes`] and `TryFrom<FdCanUSBFrame>`, and is a stable entry point,
+    /// e.g. for a `cargo fuzz` target. It never panics, whatever the input.
+    pub fn parse(line: &str) -> Result<CanFdFrame, ParseError> {
+        CanFdFrame::from_bytes(line.as_bytes())
+    }
+
+    /// Parse a `rcv` line from the FdCanUSB, directly from the bytes read.
+    ///
+    /// This avoids validating the whole line as UTF-8 and converting it to a [`FdCanUSBFrame`].
+    /// Leading and trailing whitespace, including the line terminator, is ignored.
+    ///
+    /// Errors give the byte offset of the offending token in `line`, where it is known.
+    pub fn from_bytes(line: &[u8]) -> Result<CanFdFrame, ParseError> {
+        let trimmed = line.trim_ascii();
+        // Tokens are sub-slices of `line`, so their offset is the distance between the pointers
+        let offset_of = |token: &[u8]| token.as_ptr() as usize - line.as_ptr() as usize;
+        let eol = || Some(offset_of(trimmed) + trimmed.len());
+        let mut iter = trimmed.split(|&c| c == b' ');
         match iter.next() {
-            Some("rcv") => {}
+            Some(b"rcv") => {}
             Some(unexpected) => {
                 return Err(ParseError::UnexpectedData {
                     expected: "rcv".to_string(),
-                    received: unexpected.to_string(),
+                    received: String::from_utf8_lossy(unexpected).to_string(),
+                    offset: Some(offset_of(unexpected)),
                 })
             }
             None => {
                 return Err(ParseError::UnexpectedEOL {
                     expected: "rcv".to_string(),
+                    offset: eol(),
                 })
             }
         };
 
         let id = iter.next().ok_or_else(|| ParseError::UnexpectedEOL {
             expected: "id".to_string(),
+            offset: eol(),
         })?;
 
-        let data = iter.next().ok_or_else(|| ParseError::UnexpectedEOL {
-            expected: "data".to_string(),
-        })?;
+        // A zero-length frame has an empty data field, which may also be missing at the end of the line
+        let data = iter.next().unwrap_or(&trimmed[trimmed.len()..]);
 
-        let flags: Vec<&str> = iter.collect();
+        let flags = iter;
 
-        let arbitration_id = u16::from_str_radix(id, 16).map_err(ParseError::ID)?;
+        check_hex(id, "id", false, offset_of(id))?;
+        check_hex(data, "data", true, offset_of(data))?;
 
+        let raw_id =
+            u32::from_str_radix(token_str(id, "id", offset_of(id))?, 16).map_err(ParseError::ID)?;
+
+        let data_offset = offset_of(data);
         let data = hex::decode(data)?;
 
         // E/e frame was received with extended/classic ID
@@ -170,58 +882,121 @@ impl TryFrom<FdCanUSBFrame> for CanFdFrame {
         // R/r frame was remote/data frame
         // tNNNNN timestamp of receipt measured in microseconds
         // fNN integer ID of which filter matched this frame
-        // The flag &str pass here should be a lower case character
-        let check_flag = |c: &str| -> (Option<bool>, Option<&str>) {
+        // The flag u8 pass here should be a lower case character
+        let check_flag = |c: u8| -> (Option<bool>, Option<&[u8]>) {
             flags
-                .iter()
-                .find(|x| x.to_lowercase().starts_with(c))
+                .clone()
+                .find(|x| x.first().map(u8::to_ascii_lowercase) == Some(c))
                 .map_or((None, None), |x| {
                     (
-                        Some(!x.starts_with(c)), // this is inverted as we want the UPPERCASE flag to be true
-                        x.strip_prefix(c).filter(|x| !x.is_empty()),
+                        Some(x[0] != c), // this is inverted as we want the UPPERCASE flag to be true
+                        x.strip_prefix(&[c]).filter(|x| !x.is_empty()),
                     )
                 })
         };
-        // The flag &str pass here should be a lower case character
-        let check_flag_no_data = |c: &str| -> Result<Option<bool>, ParseError> {
+        // The flag u8 pass here should be a lower case character
+        let check_flag_no_data = |c: u8| -> Result<Option<bool>, ParseError> {
             let (flag, data) = check_flag(c);
             if let Some(data) = data {
                 return Err(ParseError::UnexpectedFlagData {
-                    flag: c.to_string(),
-                    data: data.to_string(),
+                    flag: char::from(c).to_string(),
+                    data: String::from_utf8_lossy(data).to_string(),
+                    // The flag character is just before its data
+                    offset: offset_of(data) - 1,
                 });
             }
             Ok(flag)
         };
 
-        let extended_id = check_flag_no_data("e")?;
+        // The `E` and `e` flags give the id width. Without them, the id is extended if it is written with more
+        // than 3 hex digits, like the FdCanUSB sends, or does not fit in 11 bits.
+        let extended_id = match check_flag_no_data(b'e')? {
+            Some(extended_id) => extended_id,
+            None => id.len() > 3 || raw_id > u32::from(CanId::MAX_STANDARD),
+        };
+        let arbitration_id = if extended_id {
+            CanId::extended(raw_id)
+        } else {
+            u16::try_from(raw_id).ok().and_then(CanId::standard)
+        }
+        .ok_or(InvalidCanId(raw_id))?;
 
-        let brs = check_flag_no_data("b")?;
+        let brs = check_flag_no_data(b'b')?;
 
-        let fd_can_frame = check_flag_no_data("f")?;
+        let fd_can_frame = check_flag_no_data(b'f')?;
 
-        let remote_frame = check_flag_no_data("r")?;
+        // Classic frames have up to 8 bytes, and CAN FD frames are padded to a valid DLC
+        let max_len = match fd_can_frame {
+            Some(false) => MAX_CLASSIC_DATA_LEN,
+            _ => MAX_FD_DATA_LEN,
+        };
+        if data.len() > max_len || !is_valid_dlc(data.len()) {
+            return Err(ParseError::InvalidDlc {
+                len: data.len(),
+                offset: data_offset,
+            });
+        }
 
-        let (_, timestamp) = check_flag("t");
+        let remote_frame = check_flag_no_data(b'r')?;
+
+        let (_, timestamp) = check_flag(b't');
         let timestamp: Option<u32> = timestamp
-            .map(|x| x.parse())
-            .transpose()
-            .map_err(ParseError::TimeStamp)?;
+            .map(|x| {
+                token_str(x, "timestamp", offset_of(x))?
+                    .parse()
+                    .map_err(ParseError::TimeStamp)
+            })
+            .transpose()?;
 
-        // let filter_id = check_flag("f"); // will conflict with frame flag TODO: FIX
+        // let filter_id = check_flag(b'f'); // will conflict with frame flag TODO: FIX
 
         Ok(CanFdFrame {
             arbitration_id,
             data,
-            extended_id,
             brs,
             fd_can_frame,
             remote_frame,
             timestamp,
+            dlc: None,
         })
     }
 }
 
+/// Returns [`ParseError::MalformedHex`] if `token` is not hex, or has an odd length when `even` is `true`,
+/// so that the error names the `field` and echoes the token.
+fn check_hex(token: &[u8], field: &str, even: bool, offset: usize) -> Result<(), ParseError> {
+    if !token.iter().all(u8::is_ascii_hexdigit) || (even && !token.len().is_multiple_of(2)) {
+        return Err(ParseError::MalformedHex {
+            field: field.to_string(),
+            value: String::from_utf8_lossy(token).to_string(),
+            offset,
+        });
+    }
+    Ok(())
+}
+
+/// Returns [`InvalidDlc`] unless `dlc` is a valid data length, within the maximum for the frame and no shorter than its `len` bytes of data.
+fn check_dlc(dlc: u8, len: usize, fd_can_frame: Option<bool>) -> Result<(), InvalidDlc> {
+    let max = match fd_can_frame {
+        Some(false) => MAX_CLASSIC_DATA_LEN,
+        _ => MAX_FD_DATA_LEN,
+    };
+    let dlc_len = usize::from(dlc);
+    if !is_valid_dlc(dlc_len) || dlc_len > max || dlc_len < len {
+        return Err(InvalidDlc { dlc, len });
+    }
+    Ok(())
+}
+
+/// Convert a token of a line to a `&str`, so that it can be parsed.
+fn token_str<'a>(token: &'a [u8], expected: &str, offset: usize) -> Result<&'a str, ParseError> {
+    core::str::from_utf8(token).map_err(|_| ParseError::UnexpectedData {
+        expected: expected.to_string(),
+        received: String::from_utf8_lossy(token).to_string(),
+        offset: Some(offset),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,7 +1013,199 @@ mod tests {
         let encode_frame: FdCanUSBFrame = frame.into();
         assert_eq!(
             encode_frame.0,
-            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050\n".to_owned()
+            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050 E\n".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_round_up_dlc() {
+        assert_eq!(round_up_dlc(0), Some(0));
+        assert_eq!(round_up_dlc(8), Some(8));
+        assert_eq!(round_up_dlc(9), Some(12));
+        assert_eq!(round_up_dlc(12), Some(12));
+        assert_eq!(round_up_dlc(13), Some(16));
+        assert_eq!(round_up_dlc(16), Some(16));
+        assert_eq!(round_up_dlc(17), Some(20));
+        assert_eq!(round_up_dlc(20), Some(20));
+        assert_eq!(round_up_dlc(21), Some(24));
+        assert_eq!(round_up_dlc(24), Some(24));
+        assert_eq!(round_up_dlc(25), Some(32));
+        assert_eq!(round_up_dlc(32), Some(32));
+        assert_eq!(round_up_dlc(33), Some(48));
+        assert_eq!(round_up_dlc(48), Some(48));
+        assert_eq!(round_up_dlc(49), Some(64));
+        assert_eq!(round_up_dlc(64), Some(64));
+        assert_eq!(round_up_dlc(65), None);
+    }
+
+    #[test]
+    fn test_is_valid_dlc() {
+        for len in [0, 1, 8, 12, 16, 20, 24, 32, 48, 64] {
+            assert!(is_valid_dlc(len), "{len} should be valid");
+        }
+        for len in [9, 13, 17, 21, 25, 33, 49, 65] {
+            assert!(!is_valid_dlc(len), "{len} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_fdcanusb_frame_from_str() {
+        assert!("can send 8001 0102 E b\n".parse::<FdCanUSBFrame>().is_ok());
+        assert!("rcv 8001 0102 E B\r\n".parse::<FdCanUSBFrame>().is_ok());
+        assert!("OK\r\n".parse::<FdCanUSBFrame>().is_ok());
+        assert!("conf get can.bitrate\n".parse::<FdCanUSBFrame>().is_ok());
+
+        assert!(matches!(
+            "can sned 8001 0102\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::UnexpectedData { .. })
+        ));
+        assert!(matches!(
+            "sned 8001 0102\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::UnexpectedData { .. })
+        ));
+        assert!(matches!(
+            "can send 80G1 0102\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::ID(_))
+        ));
+        assert!(matches!(
+            "can send 8001 010\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::Data(_))
+        ));
+        assert!(matches!(
+            "can send\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::UnexpectedEOL { .. })
+        ));
+    }
+
+    #[test]
+    fn test_classic_frame_encode() {
+        let frame = CanFdFrame::new_classic(0x0001, &[1, 2, 3]).unwrap();
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.0, "can send 0001 010203 E f\n");
+
+        let frame = CanFdFrame::new_classic(0x0001, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.0, "can send 0001 0102030405060708 E f\n");
+
+        // A `u16` id is always extended, as it was sent with 4 hex digits before `CanId`, e.g. for moteus
+        let frame = CanFdFrame::try_from((0x0001, &[1][..])).unwrap();
+        assert_eq!(frame.dry_run().unwrap(), "can send 0001 01 E f\n");
+    }
+
+    #[test]
+    fn test_encode_classic_and_fd() {
+        let frame = CanFdFrame::new(0x8001, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(
+            frame.encode_classic().unwrap(),
+            "can send 8001 010203040506 E f\n"
+        );
+        assert_eq!(
+            frame.encode_fd().unwrap(),
+            "can send 8001 010203040506 E F\n"
+        );
+
+        let frame = frame.with_fd(false).with_brs(true).with_dlc(8);
+        assert_eq!(
+            frame.encode_classic().unwrap(),
+            "can send 8001 010203040506 E f\n"
+        );
+        assert_eq!(
+            frame.encode_fd().unwrap(),
+            "can send 8001 010203040506 E B F\n"
+        );
+
+        let frame = CanFdFrame::new(0x8001, &[0xAB; 10]).unwrap();
+        assert!(matches!(
+            frame.encode_classic(),
+            Err(EncodeError::InvalidFrameLength(InvalidFrameLength {
+                len: 10,
+                max: 8
+            }))
+        ));
+        assert_eq!(
+            frame.encode_fd().unwrap(),
+            format!("can send 8001 {}5050 E F\n", "AB".repeat(10))
+        );
+    }
+
+    #[test]
+    fn test_try_from_id_and_data() {
+        let frame = CanFdFrame::try_from((0x8001, &[1, 2, 3, 4][..])).unwrap();
+        assert_eq!(frame.fd_can_frame, Some(false));
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.0, "can send 8001 01020304 E f\n");
+
+        let frame = CanFdFrame::try_from((CanId::Standard(0x001), &[0xAB; 20][..])).unwrap();
+        assert_eq!(frame.fd_can_frame, Some(true));
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(
+            encode_frame.0,
+            format!("can send 001 {} e F\n", "AB".repeat(20))
+        );
+
+        let error = CanFdFrame::try_from((0x8001, &[0; 65][..])).unwrap_err();
+        assert_eq!((error.len, error.max), (65, 64));
+    }
+
+    #[test]
+    fn test_classic_frame_length() {
+        let error = CanFdFrame::new_classic(0x0001, &[0; 9]).unwrap_err();
+        assert_eq!((error.len, error.max), (9, 8));
+        let error = CanFdFrame::new_with_flags(0x0001, &[0; 12], None, Some(false), None, None)
+            .unwrap_err();
+        assert_eq!((error.len, error.max), (12, 8));
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let frames = [
+            CanFdFrame::new(0x8001, &[1, 2, 3]).unwrap(),
+            CanFdFrame::new(0x8001, &[0xAB; 21]).unwrap(),
+            CanFdFrame::new_with_flags(
+                0x0001,
+                &[0xCD; 64],
+                Some(true),
+                Some(true),
+                Some(false),
+                None,
+            )
+            .unwrap(),
+            CanFdFrame::new_classic(0x0001, &[1, 2, 3]).unwrap(),
+        ];
+        for frame in frames {
+            let mut out = [0; CanFdFrame::MAX_ENCODED_LEN];
+            let len = frame.encode_into(&mut out).expect("Failed to encode frame");
+            let encode_frame: FdCanUSBFrame = frame.into();
+            assert_eq!(&out[..len], encode_frame.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_encode_into_buffer_too_small() {
+        let frame = CanFdFrame::new(0x8001, &[1, 2, 3]).unwrap();
+        let mut out = [0; 16];
+        assert!(matches!(
+            frame.encode_into(&mut out),
+            Err(EncodeError::BufferTooSmall {
+                required: 23,
+                available: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn test_can_fd_frame_from_bytes() {
+        let line = b"rcv 8001 01000A0D E B F r t0100\r\n";
+        let frame = CanFdFrame::from_bytes(line).expect("Failed to decode frame");
+        let decode_frame: CanFdFrame = FdCanUSBFrame::from("rcv 8001 01000A0D E B F r t0100\r\n")
+            .try_into()
+            .expect("Failed to decode frame");
+        assert_eq!(frame, decode_frame);
+        assert_eq!(frame.data, vec![0x01, 0x00, 0x0A, 0x0D]);
+        assert_eq!(frame.timestamp, Some(100));
+        assert_eq!(
+            frame.timestamp_duration(),
+            Some(core::time::Duration::from_micros(100))
         );
     }
 
@@ -247,7 +1214,7 @@ mod tests {
         let frame =
             FdCanUSBFrame("rcv 8001 01000A0D200000C07F0D270000004011001F01130D505050\n".to_owned());
         let decode_frame: CanFdFrame = frame.try_into().expect("Failed to decode frame");
-        assert_eq!(decode_frame.arbitration_id, 0x8001);
+        assert_eq!(decode_frame.arbitration_id.as_raw(), 0x8001);
         assert_eq!(
             decode_frame.data,
             vec![
@@ -264,7 +1231,6 @@ mod tests {
             &[
                 1, 0, 10, 13, 32, 0, 0, 192, 127, 13, 39, 0, 0, 0, 64, 17, 0, 31, 1, 19, 13,
             ],
-            None,
             Some(true),
             None,
             None,
@@ -274,18 +1240,33 @@ mod tests {
         let encode_frame: FdCanUSBFrame = frame.into();
         assert_eq!(
             encode_frame.0,
-            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050 B\n".to_owned()
+            "can send 8001 01000A0D200000C07F0D270000004011001F01130D505050 E B\n".to_owned()
         );
     }
 
+    #[test]
+    fn test_can_fd_frame_with_flags() {
+        let frame = CanFdFrame::new(0x8001, &[1, 2])
+            .unwrap()
+            .with_brs(true)
+            .with_fd(true)
+            .with_remote(false);
+        let expected =
+            CanFdFrame::new_with_flags(0x8001, &[1, 2], Some(true), Some(true), Some(false), None)
+                .unwrap();
+        assert_eq!(frame, expected);
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.0, "can send 8001 0102 E B F r\n");
+    }
+
     #[test]
     fn test_can_fd_frame_flags_decode() {
         let frame = FdCanUSBFrame(
-            "rcv 8001 01000A0D200000C07F0D270000004011001F01130D505050 e B F r f-1 t0100"
+            "rcv 8001 01000A0D200000C07F0D270000004011001F01130D505050 E B F r f-1 t0100"
                 .to_owned(),
         );
         let decode_frame: CanFdFrame = frame.try_into().expect("Failed to decode frame");
-        assert_eq!(decode_frame.arbitration_id, 0x8001);
+        assert_eq!(decode_frame.arbitration_id.as_raw(), 0x8001);
         assert_eq!(
             decode_frame.data,
             vec![
@@ -295,4 +1276,452 @@ mod tests {
         );
         assert_eq!(decode_frame.brs, Some(true));
     }
+
+    #[test]
+    fn test_can_id_encode() {
+        let frame = CanFdFrame::new(CanId::standard(0x7FF).unwrap(), &[1]).unwrap();
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.0, "can send 7FF 01 e\n");
+
+        let frame = CanFdFrame::new(CanId::extended(0x1FFF_FFFF).unwrap(), &[1]).unwrap();
+        let mut out = [0; CanFdFrame::MAX_ENCODED_LEN];
+        let len = frame.encode_into(&mut out).unwrap();
+        assert_eq!(&out[..len], b"can send 1FFFFFFF 01 E\n");
+
+        // Ids out of range are rejected, rather than truncated to a different id
+        for id in [CanId::Standard(0x8001), CanId::Extended(0xFFFF_FFFF)] {
+            let frame = CanFdFrame::new(id, &[1]).unwrap();
+            assert!(matches!(frame.dry_run(), Err(EncodeError::InvalidId(_))));
+            assert!(matches!(frame.encode_fd(), Err(EncodeError::InvalidId(_))));
+            assert!(matches!(
+                frame.validate(),
+                Err(FrameValidationError::InvalidId(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_can_id_decode() {
+        let frame = CanFdFrame::from_bytes(b"rcv 123 01").unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Standard(0x123));
+        let frame = CanFdFrame::from_bytes(b"rcv 0123 01").unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Extended(0x123));
+        let frame = CanFdFrame::from_bytes(b"rcv 0123 01 e").unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Standard(0x123));
+        let frame = CanFdFrame::from_bytes(b"rcv 123 01 E").unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Extended(0x123));
+        let frame = CanFdFrame::from_bytes(b"rcv 1FFFFFFF 00 E").unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Extended(0x1FFF_FFFF));
+        assert_eq!(frame.data, [0x00]);
+        let frame: CanFdFrame = FdCanUSBFrame::from("rcv 1fffffff 00 E\r\n")
+            .try_into()
+            .unwrap();
+        assert_eq!(frame.arbitration_id, CanId::Extended(CanId::MAX_EXTENDED));
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv 20000000 01"),
+            Err(ParseError::InvalidId(_))
+        ));
+        // A standard id must fit in 11 bits, whatever the number of digits
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv 8001 01 e"),
+            Err(ParseError::InvalidId(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_frame() {
+        let frame = CanFdFrame::new(0x8001, &[]).unwrap();
+        let encode_frame: FdCanUSBFrame = frame.clone().into();
+        assert_eq!(encode_frame.0, "can send 8001  E\n");
+        let encode_frame: FdCanUSBFrame = frame.with_brs(true).into();
+        assert_eq!(encode_frame.0, "can send 8001  E B\n");
+
+        for line in [&b"rcv 8001"[..], b"rcv 8001 \r\n", b"rcv 8001  B"] {
+            let frame = CanFdFrame::from_bytes(line).expect("Failed to decode frame");
+            assert_eq!(frame.arbitration_id.as_raw(), 0x8001);
+            assert!(frame.data.is_empty());
+        }
+        let frame = CanFdFrame::from_bytes(b"rcv 8001  B").unwrap();
+        assert_eq!(frame.brs, Some(true));
+        assert!("can send 8001  E\n".parse::<FdCanUSBFrame>().is_ok());
+    }
+
+    #[test]
+    fn test_fdcanusb_frame_text() {
+        let frame: FdCanUSBFrame = CanFdFrame::new(0x8001, &[1, 2])
+            .unwrap()
+            .with_brs(true)
+            .into();
+        assert_eq!(frame.as_str(), "can send 8001 0102 E B\n");
+        assert_eq!(frame.to_string(), frame.as_str());
+        assert_eq!(frame.as_ref(), frame.as_str());
+        assert_eq!(frame.into_string(), "can send 8001 0102 E B\n");
+    }
+
+    #[test]
+    fn test_builder() {
+        let frame = CanFdFrame::builder(0x8001)
+            .data(&[1, 2])
+            .brs(true)
+            .fd(true)
+            .remote(false)
+            .build()
+            .unwrap();
+        let encode_frame: FdCanUSBFrame = frame.into();
+        assert_eq!(encode_frame.as_str(), "can send 8001 0102 E B F r\n");
+
+        let builder = CanFdFrame::builder(0x8001).data(&[0; 9]).brs(false);
+        assert!(matches!(
+            builder.clone().fd(true).build(),
+            Err(BuildError::MissingFlag("remote"))
+        ));
+        assert!(matches!(
+            builder.clone().fd(false).remote(false).build(),
+            Err(BuildError::InvalidFrameLength(_))
+        ));
+        assert!(matches!(
+            builder.fd(true).remote(true).build(),
+            Err(BuildError::ConflictingFlags(_))
+        ));
+        assert!(matches!(
+            CanFdFrame::builder(0x8001).build(),
+            Err(BuildError::MissingData)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_dlc() {
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv 8001 01020304050607080910 F"),
+            Err(ParseError::InvalidDlc { len: 10, offset: 9 })
+        ));
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv 8001 000102030405060708090A0B f"),
+            Err(ParseError::InvalidDlc { len: 12, offset: 9 })
+        ));
+        let frame = CanFdFrame::from_bytes(b"rcv 8001 000102030405060708090A0B F").unwrap();
+        assert_eq!(frame.data.len(), 12);
+    }
+
+    #[test]
+    fn test_frame_ord() {
+        use alloc::collections::BTreeSet;
+        let frames: BTreeSet<_> = [
+            CanFdFrame::new(0x8001, &[2]).unwrap(),
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+            CanFdFrame::new(CanId::Standard(0x7FF), &[3]).unwrap(),
+            CanFdFrame::new(0x8001, &[1]).unwrap(),
+        ]
+        .into_iter()
+        .collect();
+        let ids: alloc::vec::Vec<_> = frames
+            .iter()
+            .map(|frame| (frame.arbitration_id, frame.data[0]))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                (CanId::Standard(0x7FF), 3),
+                (CanId::Extended(0x8001), 1),
+                (CanId::Extended(0x8001), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let frame = CanFdFrame::new(0x8001, &[1, 2, 3, 4, 5])
+            .unwrap()
+            .with_brs(true);
+        assert_eq!(frame.dry_run().unwrap(), "can send 8001 0102030405 E B\n");
+        let mut frame = CanFdFrame::new(0x8001, &[0; 9]).unwrap().with_fd(false);
+        assert!(matches!(
+            frame.dry_run(),
+            Err(EncodeError::InvalidFrameLength(InvalidFrameLength {
+                len: 9,
+                max: 8
+            }))
+        ));
+        frame.data = vec![0; 65];
+        frame.fd_can_frame = None;
+        assert!(matches!(
+            frame.dry_run(),
+            Err(EncodeError::InvalidFrameLength(InvalidFrameLength {
+                len: 65,
+                max: 64
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_remote_frame() {
+        let frame = CanFdFrame::new_remote(0x8001, 8).unwrap();
+        assert_eq!(frame.dlc(), 8);
+        assert_eq!(
+            FdCanUSBFrame::from(frame.clone()).as_str(),
+            "can send 8001 0000000000000000 E f R\n"
+        );
+        let decoded = CanFdFrame::from_bytes(b"rcv 8001 0000000000000000 f R").unwrap();
+        assert_eq!(decoded, frame);
+        assert!(matches!(
+            CanFdFrame::new_remote(0x8001, 9),
+            Err(InvalidFrameLength { len: 9, max: 8 })
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        assert!(frame.validate().is_ok());
+        assert!(CanFdFrame::new_remote(0x8001, 8)
+            .unwrap()
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            frame.clone().with_remote(true).validate(),
+            Err(FrameValidationError::RemoteWithData)
+        ));
+        assert!(matches!(
+            CanFdFrame::new(0x8001, &[])
+                .unwrap()
+                .with_remote(true)
+                .with_fd(true)
+                .validate(),
+            Err(FrameValidationError::RemoteFd)
+        ));
+        assert!(matches!(
+            frame.clone().with_fd(false).with_brs(true).validate(),
+            Err(FrameValidationError::BrsWithoutFd)
+        ));
+        assert!(matches!(
+            CanFdFrame::new(0x8001, &[0; 20])
+                .unwrap()
+                .with_fd(false)
+                .validate(),
+            Err(FrameValidationError::InvalidFrameLength(_))
+        ));
+        assert!(matches!(
+            frame
+                .with_brs(true)
+                .validate_with_defaults((None, Some(false), None)),
+            Err(FrameValidationError::BrsWithoutFd)
+        ));
+    }
+
+    #[test]
+    fn test_segment() {
+        let data: Vec<u8> = (0..=200).collect();
+        let frames: Vec<_> = segment(CanId::Standard(0x123), &data).collect();
+        assert_eq!(frames.len(), 4);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.arbitration_id == CanId::Standard(0x123)));
+        let lens: Vec<_> = frames.iter().map(|frame| frame.data.len()).collect();
+        assert_eq!(lens, [64, 64, 64, 9]);
+        let reassembled: Vec<u8> = frames.iter().flat_map(|frame| frame.data.clone()).collect();
+        assert_eq!(reassembled, data);
+        assert_eq!(segment(0x8001, &[]).count(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_parse_log() {
+        let log =
+            b"rcv 0100 01\r\n\nOK\nOK 1234\r\ncan send 8001 01 E\r\nERR bad\r\nrcv 0100 02 t10";
+        let frames: Vec<_> = parse_log(&log[..]).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].as_ref().unwrap().data, [1]);
+        match &frames[1] {
+            Err(ParseError::Line { line: 6, source }) => {
+                assert!(matches!(**source, ParseError::UnexpectedData { .. }))
+            }
+            result => panic!("unexpected {result:?}"),
+        }
+        assert_eq!(frames[2].as_ref().unwrap().timestamp, Some(10));
+    }
+
+    #[test]
+    fn test_dlc_override() {
+        let frame = CanFdFrame::new(0x8001, &[1, 2, 3]).unwrap();
+        assert_eq!(frame.dry_run().unwrap(), "can send 8001 010203 E\n");
+        let frame = frame.with_dlc(12);
+        assert_eq!(frame.dlc(), 12);
+        assert_eq!(
+            frame.dry_run().unwrap(),
+            "can send 8001 010203505050505050505050 E\n"
+        );
+        let classic = CanFdFrame::new_classic(0x8001, &[1, 2, 3]).unwrap();
+        assert_eq!(
+            classic.with_dlc(5).dry_run().unwrap(),
+            "can send 8001 0102035050 E f\n"
+        );
+        for (frame, dlc) in [
+            (CanFdFrame::new(0x8001, &[1, 2, 3]).unwrap(), 13),
+            (CanFdFrame::new(0x8001, &[0; 12]).unwrap(), 8),
+            (CanFdFrame::new_classic(0x8001, &[1]).unwrap(), 12),
+        ] {
+            let frame = frame.with_dlc(dlc);
+            assert!(matches!(
+                frame.dry_run(),
+                Err(EncodeError::InvalidDlc(InvalidDlc { dlc: d, .. })) if d == dlc
+            ));
+            assert!(matches!(
+                frame.validate(),
+                Err(FrameValidationError::InvalidDlc(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_source_id() {
+        let reply = CanFdFrame::new(0x0100, &[]).unwrap();
+        assert_eq!(reply.source_id(), 1);
+        assert!(reply.is_from(1) && !reply.is_from(0));
+        // The reply bit is not part of the source
+        let query = CanFdFrame::new(0x8001, &[]).unwrap();
+        assert_eq!(query.source_id(), 0);
+    }
+
+    /// A xorshift generator, so the round trip test covers many frames without a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn flag(&mut self) -> Option<bool> {
+            [None, Some(false), Some(true)][(self.next() % 3) as usize]
+        }
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        let mut checked = 0;
+        while checked < 2000 {
+            let arbitration_id = if rng.next().is_multiple_of(2) {
+                CanId::Standard((rng.next() % 0x800) as u16)
+            } else {
+                CanId::Extended((rng.next() % 0x2000_0000) as u32)
+            };
+            let remote_frame = rng.flag();
+            let len = (rng.next() % 65) as usize;
+            let data: Vec<u8> = (0..len)
+                .map(|_| match remote_frame {
+                    Some(true) => 0,
+                    _ => rng.next() as u8,
+                })
+                .collect();
+            let frame = CanFdFrame {
+                arbitration_id,
+                data,
+                brs: rng.flag(),
+                fd_can_frame: rng.flag(),
+                remote_frame,
+                ..Default::default()
+            };
+            if frame.validate().is_err() {
+                continue;
+            }
+            checked += 1;
+
+            let encoded = frame.dry_run().unwrap();
+            let line = encoded.replacen("can send", "rcv", 1);
+            let parsed = CanFdFrame::parse(&line)
+                .unwrap_or_else(|e| panic!("failed to parse {line:?}: {e}"));
+            assert_eq!(parsed.arbitration_id, frame.arbitration_id, "{line:?}");
+            assert_eq!(
+                (parsed.brs, parsed.fd_can_frame, parsed.remote_frame),
+                (frame.brs, frame.fd_can_frame, frame.remote_frame),
+                "{line:?}"
+            );
+            // CAN FD frames are padded to a valid DLC, with zeros for a remote frame
+            let padding = if remote_frame == Some(true) { 0 } else { 0x50 };
+            assert_eq!(parsed.data[..frame.data.len()], frame.data, "{line:?}");
+            assert!(parsed.data[frame.data.len()..]
+                .iter()
+                .all(|&b| b == padding));
+            assert_eq!(parsed.timestamp, None);
+        }
+    }
+
+    #[test]
+    fn test_parse_never_panics() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        let alphabet = b"rcv 0123456789abcdefEBFRebfrtx \r\n";
+        for _ in 0..10_000 {
+            let len = (rng.next() % 40) as usize;
+            let line: String = (0..len)
+                .map(|_| char::from(alphabet[(rng.next() % alphabet.len() as u64) as usize]))
+                .collect();
+            let _ = CanFdFrame::parse(&format!("rcv {line}"));
+        }
+    }
+
+    #[test]
+    fn test_set_data() {
+        let mut frame = CanFdFrame::new(0x8001, &[1, 2]).unwrap();
+        frame.data_mut()[1] = 3;
+        assert_eq!(frame.data(), [1, 3]);
+        frame.set_data(&[0; 64]).unwrap();
+        assert_eq!(frame.data().len(), 64);
+        assert!(matches!(
+            frame.set_data(&[0; 65]),
+            Err(InvalidFrameLength { len: 65, max: 64 })
+        ));
+        let mut frame = CanFdFrame::new_classic(0x8001, &[1]).unwrap();
+        assert!(matches!(
+            frame.set_data(&[0; 9]),
+            Err(InvalidFrameLength { len: 9, max: 8 })
+        ));
+        assert_eq!(frame.data(), [1]);
+    }
+
+    #[test]
+    fn test_malformed_hex() {
+        let error = CanFdFrame::from_bytes(b"rcv 80G1 0102").unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::MalformedHex { ref field, ref value, offset: 4 } if field == "id" && value == "80G1"
+        ));
+        let frame = FdCanUSBFrame::from("rcv 8001 01020\r\n");
+        let error = CanFdFrame::try_from(frame).unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::MalformedHex { ref field, ref value, offset: 9 } if field == "data" && value == "01020"
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_offset() {
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv 8001 01 r b1"),
+            Err(ParseError::UnexpectedFlagData { offset: 14, .. })
+        ));
+        assert!(matches!(
+            CanFdFrame::from_bytes(b" OK\r\n"),
+            Err(ParseError::UnexpectedData {
+                offset: Some(1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            CanFdFrame::from_bytes(b"rcv\r\n"),
+            Err(ParseError::UnexpectedEOL {
+                offset: Some(3),
+                ..
+            })
+        ));
+        assert!(matches!(
+            "can send\n".parse::<FdCanUSBFrame>(),
+            Err(ParseError::UnexpectedEOL {
+                offset: Some(8),
+                ..
+            })
+        ));
+    }
 }
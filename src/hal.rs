@@ -0,0 +1,104 @@
+//! Implementations of the [`embedded_can`] traits.
+
+use crate::error::InvalidCanId;
+use crate::frames::CanFdFrame;
+use crate::id::CanId;
+
+impl TryFrom<CanId> for embedded_can::Id {
+    type Error = InvalidCanId;
+    /// Returns an `Err` if the id is out of range, see [`CanId::is_valid`].
+    fn try_from(id: CanId) -> Result<Self, Self::Error> {
+        let invalid = InvalidCanId(id.as_raw());
+        match id {
+            CanId::Standard(id) => embedded_can::StandardId::new(id)
+                .map(Into::into)
+                .ok_or(invalid),
+            CanId::Extended(id) => embedded_can::ExtendedId::new(id)
+                .map(Into::into)
+                .ok_or(invalid),
+        }
+    }
+}
+
+impl From<embedded_can::Id> for CanId {
+    fn from(id: embedded_can::Id) -> Self {
+        match id {
+            embedded_can::Id::Standard(id) => CanId::Standard(id.as_raw()),
+            embedded_can::Id::Extended(id) => CanId::Extended(id.as_raw()),
+        }
+    }
+}
+
+/// Allows generic code written against [`embedded_can::Frame`] to create and read frames.
+///
+/// Frames created with [`Frame::new`](embedded_can::Frame::new) are the same as [`CanFdFrame::new`], so they may have up to 64 bytes.
+/// Remote frames are classic frames, and are sent with `dlc` zero bytes of data.
+impl embedded_can::Frame for CanFdFrame {
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        CanFdFrame::new(CanId::from(id.into()), data).ok()
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        CanFdFrame::new_remote(CanId::from(id.into()), dlc).ok()
+    }
+
+    fn is_extended(&self) -> bool {
+        self.arbitration_id.is_extended()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote_frame == Some(true)
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        // `Frame::id` can not fail, so an out of range id, which is rejected when the frame is written, is masked to its width
+        let id = match self.arbitration_id {
+            CanId::Standard(id) => CanId::Standard(id & CanId::MAX_STANDARD),
+            CanId::Extended(id) => CanId::Extended(id & CanId::MAX_EXTENDED),
+        };
+        id.try_into().expect("masked ids are in range")
+    }
+
+    fn dlc(&self) -> usize {
+        CanFdFrame::dlc(self)
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.is_remote_frame() {
+            &[]
+        } else {
+            &self.data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_can::{ExtendedId, Frame, StandardId};
+
+    #[test]
+    fn test_embedded_can_frame() {
+        let frame = <CanFdFrame as Frame>::new(StandardId::new(0x123).unwrap(), &[1, 2]).unwrap();
+        assert!(frame.is_standard());
+        assert_eq!(frame.arbitration_id, CanId::Standard(0x123));
+        assert_eq!(frame.data(), [1, 2]);
+
+        let frame = <CanFdFrame as Frame>::new_remote(ExtendedId::new(0x8001).unwrap(), 4).unwrap();
+        assert!(frame.is_extended() && frame.is_remote_frame());
+        assert_eq!(
+            frame.id(),
+            embedded_can::Id::Extended(ExtendedId::new(0x8001).unwrap())
+        );
+        assert_eq!((frame.dlc(), Frame::data(&frame)), (4, &[][..]));
+        assert!(<CanFdFrame as Frame>::new_remote(ExtendedId::new(0x8001).unwrap(), 9).is_none());
+
+        assert!(embedded_can::Id::try_from(CanId::Standard(0x800)).is_err());
+        assert!(embedded_can::Id::try_from(CanId::Extended(0x2000_0000)).is_err());
+        let frame = CanFdFrame::new(CanId::Standard(0x801), &[]).unwrap();
+        assert_eq!(
+            frame.id(),
+            embedded_can::Id::Standard(StandardId::new(0x001).unwrap())
+        );
+    }
+}
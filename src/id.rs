@@ -0,0 +1,147 @@
+use crate::error::InvalidCanId;
+
+/// The arbitration id of a CAN frame, either an 11-bit standard id or a 29-bit extended id.
+///
+/// Frames are sent with the `e` flag if the id is standard, and `E` if it is extended,
+/// and the id is written with 3 hex digits if it is standard, and at least 4 if it is extended,
+/// as the FdCanUSB takes an id with more than 3 hex digits as extended when there is no flag.
+/// A received frame's id is extended if it has the `E` flag, or has no flag and more than 3 hex digits.
+///
+/// Ids are ordered with every standard id before every extended id, then by value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CanId {
+    /// An 11-bit standard id.
+    Standard(u16),
+    /// A 29-bit extended id.
+    Extended(u32),
+}
+
+impl CanId {
+    /// The largest standard id.
+    pub const MAX_STANDARD: u16 = 0x7FF;
+    /// The largest extended id.
+    pub const MAX_EXTENDED: u32 = 0x1FFF_FFFF;
+    /// The flag DBC files set in the message id of an extended id.
+    const DBC_EXTENDED: u32 = 1 << 31;
+
+    /// Create a standard id, returns `None` if `id` is larger than 11 bits.
+    pub fn standard(id: u16) -> Option<CanId> {
+        (id <= Self::MAX_STANDARD).then_some(CanId::Standard(id))
+    }
+
+    /// Create an extended id, returns `None` if `id` is larger than 29 bits.
+    pub fn extended(id: u32) -> Option<CanId> {
+        (id <= Self::MAX_EXTENDED).then_some(CanId::Extended(id))
+    }
+
+    /// The raw value of the id.
+    pub fn as_raw(&self) -> u32 {
+        match *self {
+            CanId::Standard(id) => u32::from(id),
+            CanId::Extended(id) => id,
+        }
+    }
+
+    /// Returns `true` if the id fits in 11 bits if it is standard, or 29 bits if it is extended.
+    ///
+    /// The variants can be created with any value, so frames check this before they are encoded.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            CanId::Standard(id) => id <= Self::MAX_STANDARD,
+            CanId::Extended(id) => id <= Self::MAX_EXTENDED,
+        }
+    }
+
+    /// Returns [`InvalidCanId`] unless the id is valid, see [`CanId::is_valid`].
+    pub(crate) fn check(&self) -> Result<(), InvalidCanId> {
+        if !self.is_valid() {
+            return Err(InvalidCanId(self.as_raw()));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if this is an extended id.
+    pub fn is_extended(&self) -> bool {
+        matches!(self, CanId::Extended(_))
+    }
+
+    /// The id as a DBC file message id, which has bit 31 set if the id is extended.
+    ///
+    /// DBC decoders look up messages by this id, so it can be used to decode [`CanFdFrame::as_raw`](crate::CanFdFrame::as_raw) data.
+    pub fn to_dbc_id(&self) -> u32 {
+        match *self {
+            CanId::Standard(id) => u32::from(id),
+            CanId::Extended(id) => id | Self::DBC_EXTENDED,
+        }
+    }
+
+    /// Create an id from a DBC file message id, which is extended if bit 31 is set, see [`CanId::to_dbc_id`].
+    ///
+    /// Returns an `Err` if the id is larger than 29 bits, or 11 bits without bit 31.
+    pub fn from_dbc_id(id: u32) -> Result<CanId, InvalidCanId> {
+        if id & Self::DBC_EXTENDED != 0 {
+            CanId::extended(id & !Self::DBC_EXTENDED).ok_or(InvalidCanId(id))
+        } else {
+            u16::try_from(id)
+                .ok()
+                .and_then(CanId::standard)
+                .ok_or(InvalidCanId(id))
+        }
+    }
+}
+
+impl Default for CanId {
+    fn default() -> Self {
+        CanId::Extended(0)
+    }
+}
+
+impl From<u16> for CanId {
+    /// Create an extended id.
+    ///
+    /// This matches how `u16` ids were always sent, as 4 hex digits, which moteus controllers expect.
+    /// Use [`CanId::standard`] to create a standard id.
+    fn from(id: u16) -> Self {
+        CanId::Extended(u32::from(id))
+    }
+}
+
+impl TryFrom<u32> for CanId {
+    type Error = InvalidCanId;
+    /// Create an extended id, returns an `Err` if `id` is larger than 29 bits.
+    fn try_from(id: u32) -> Result<Self, Self::Error> {
+        CanId::extended(id).ok_or(InvalidCanId(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_id_range() {
+        assert_eq!(CanId::standard(0x7FF), Some(CanId::Standard(0x7FF)));
+        assert_eq!(CanId::standard(0x800), None);
+        assert_eq!(
+            CanId::extended(0x1FFF_FFFF),
+            Some(CanId::Extended(0x1FFF_FFFF))
+        );
+        assert_eq!(CanId::extended(0x2000_0000), None);
+        assert!(CanId::try_from(0x2000_0000u32).is_err());
+        assert_eq!(CanId::from(0x8001u16).as_raw(), 0x8001);
+        assert!(!CanId::Standard(0x800).is_valid());
+        assert!(!CanId::Extended(0x2000_0000).is_valid());
+        assert!(CanId::from(0x0001u16).is_extended());
+    }
+
+    #[test]
+    fn test_dbc_id() {
+        assert_eq!(CanId::Standard(0x123).to_dbc_id(), 0x123);
+        assert_eq!(CanId::Extended(0x123).to_dbc_id(), 0x8000_0123);
+        for id in [CanId::Standard(0x7FF), CanId::Extended(0x1FFF_FFFF)] {
+            assert_eq!(CanId::from_dbc_id(id.to_dbc_id()).unwrap(), id);
+        }
+        assert!(CanId::from_dbc_id(0x800).is_err());
+        assert!(CanId::from_dbc_id(0xA000_0000).is_err());
+    }
+}
@@ -3,7 +3,7 @@
 //!
 //! This initial release is open for feedback and may change implementation details.
 //! ### Example
-//! ```
+//! ```no_run
 //! use fdcanusb::{FdCanUSB, serial2};
 //! # fn main() -> Result<(), std::io::Error> {
 //! let transport = serial2::SerialPort::open("/dev/fdcanusb", serial2::KeepSettings)?;
@@ -14,19 +14,88 @@
 //!
 //! ### Features
 //!
-//! - `default = ["serial2"]`
+//! - `default = ["std", "serial2", "log"]`
+//! - `std`
+//!     - Enables the [`FdCanUSB`] struct and its IO error types.
+//!       Without it the crate is `no_std`, and only the frame encoding and decoding is available (requires `alloc`).
 //! - `serial2`
 //!     - Enables re-exporting of the [`serial2`] crate and the [`FdCanUSB::open`] fn.
+//! - `log`
+//!     - Enables logging of frames with the [`log`](https://docs.rs/log) crate.
+//! - `tracing`
+//!     - Enables logging with the [`tracing`](https://docs.rs/tracing) crate instead of `log`.
+//!       Frames are `debug` events with `direction`, `arbitration_id` and `data_len` fields.
+//! - `embedded-io`
+//!     - Enables the [`EmbeddedIo`] adapter, to use a transport implementing the `embedded-io` traits.
+//!       The [`FdCanUSB`] still requires `std`.
+//! - `embedded-can`
+//!     - Implements the [`embedded_can::Frame`](https://docs.rs/embedded-can) trait for [`CanFdFrame`], and conversions between [`CanId`] and `embedded_can::Id`.
+//!       This does not require `std`.
+//! - `record`
+//!     - Enables the [`RecordTransport`] wrapper, which records a session to a file, and the [`ReplayTransport`] to replay it offline.
+//! - `moteus`
+//!     - Enables the [`moteus`] module, to read and write the registers of a moteus controller.
+//! - `framed`
+//!     - Enables the [`FramedTransport`] wrapper, which adds a length and CRC to each line for noisy links, e.g. a TCP bridge.
+//!       Both ends of the link must use it.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 #[macro_use]
 mod log;
+mod binary;
+#[cfg(feature = "std")]
 mod bus;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "embedded-io")]
+mod embedded;
 mod error;
+mod flags;
+#[cfg(feature = "framed")]
+mod framed;
 mod frames;
+#[cfg(feature = "embedded-can")]
+mod hal;
+mod id;
+#[cfg(feature = "moteus")]
+pub mod moteus;
+#[cfg(feature = "std")]
+mod multi;
+#[cfg(feature = "record")]
+mod record;
+#[cfg(feature = "std")]
+mod stats;
 
-pub use bus::FdCanUSB;
+#[cfg(feature = "std")]
+pub use bus::{
+    Ack, BatchWriter, Direction, ExpectResponse, FdCanUSB, FdCanUSBReader, FdCanUSBWriter,
+    FrameSender, Incoming, LineEnding, PendingWrite, WriteStatus,
+};
+#[cfg(feature = "serial2")]
+pub use bus::{USB_PID, USB_VID};
+#[cfg(feature = "std")]
+pub use clock::{Clock, SystemClock};
+#[cfg(feature = "embedded-io")]
+pub use embedded::EmbeddedIo;
 pub use error::*;
-pub use frames::{CanFdFrame, FdCanUSBFrame};
+pub use flags::{FrameFlag, FrameFlags};
+#[cfg(feature = "framed")]
+pub use framed::FramedTransport;
+#[cfg(feature = "std")]
+pub use frames::parse_log;
+pub use frames::{
+    is_valid_dlc, round_up_dlc, segment, CanFdFrame, CanFdFrameBuilder, FdCanUSBFrame,
+};
+pub use id::CanId;
+#[cfg(feature = "std")]
+pub use multi::FdCanUSBBus;
+#[cfg(feature = "record")]
+pub use record::{RecordTransport, ReplayTransport};
+#[cfg(feature = "std")]
+pub use stats::Stats;
 
 #[cfg(feature = "serial2")]
 pub use serial2;
@@ -1,4 +1,4 @@
-#[cfg(feature = "log")]
+#[cfg(all(feature = "log", not(feature = "tracing")))]
 #[allow(unused)]
 #[macro_use]
 mod details {
@@ -33,7 +33,44 @@ mod details {
 	}
 }
 
-#[cfg(not(feature = "log"))]
+#[cfg(feature = "tracing")]
+#[allow(unused)]
+#[macro_use]
+mod details {
+    // `tracing` takes precedence over `log` if both features are enabled.
+
+    macro_rules! trace {
+		($($args:tt)*) => {{
+			::tracing::trace!($($args)*);
+		}}
+	}
+
+    macro_rules! debug {
+		($($args:tt)*) => {{
+			::tracing::debug!($($args)*);
+		}}
+	}
+
+    macro_rules! info {
+		($($args:tt)*) => {{
+			::tracing::info!($($args)*);
+		}}
+	}
+
+    macro_rules! warn {
+		($($args:tt)*) => {{
+			::tracing::warn!($($args)*);
+		}}
+	}
+
+    macro_rules! error {
+		($($args:tt)*) => {{
+			::tracing::error!($($args)*);
+		}}
+	}
+}
+
+#[cfg(not(any(feature = "log", feature = "tracing")))]
 #[allow(unused)]
 #[macro_use]
 mod details {
@@ -0,0 +1,372 @@
+//! Reading and writing the registers of a [moteus](https://mjbots.com/products/moteus-r4-11) controller.
+//!
+//! moteus frames are sent from `source` to `destination` with the arbitration id `source << 8 | destination`,
+//! and the `0x8000` bit set if a reply is requested, see [`FdCanUSB::query`].
+//! The data is a list of subframes, each a command byte, a starting register and any values,
+//! padded with `0x50` (no-op) bytes to a valid DLC.
+//!
+//! Only the mode, position, velocity and torque registers are supported,
+//! build other commands with [`CanFdFrame`] directly.
+//! ```no_run
+//! use fdcanusb::moteus::{Register, Resolution};
+//! use fdcanusb::{serial2, FdCanUSB};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut fdcanusb = FdCanUSB::open("/dev/fdcanusb", serial2::KeepSettings)?;
+//! let position = fdcanusb.read_register(1, Register::Position, Resolution::Float)?;
+//! println!("position: {position} rev");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{MoteusError, TransferError};
+use crate::frames::{round_up_dlc, CanFdFrame};
+use crate::FdCanUSB;
+
+/// The command of a register subframe, in the upper bits of its first byte.
+const WRITE: u8 = 0x00;
+const READ: u8 = 0x10;
+const REPLY: u8 = 0x20;
+/// The whole first byte of an error or padding subframe.
+const WRITE_ERROR: u8 = 0x30;
+const READ_ERROR: u8 = 0x31;
+const NOP: u8 = 0x50;
+
+/// The `0x8000` bit of the arbitration id, set if a reply is requested.
+const QUERY: u32 = 0x8000;
+
+/// The size and encoding of a register value, which sets its range and precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Int8,
+    Int16,
+    Int32,
+    Float,
+}
+
+impl Resolution {
+    /// The type bits of a subframe's command byte.
+    fn bits(self) -> u8 {
+        match self {
+            Resolution::Int8 => 0,
+            Resolution::Int16 => 1,
+            Resolution::Int32 => 2,
+            Resolution::Float => 3,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Resolution {
+        match bits & 0b11 {
+            0 => Resolution::Int8,
+            1 => Resolution::Int16,
+            2 => Resolution::Int32,
+            _ => Resolution::Float,
+        }
+    }
+
+    /// The number of bytes of a value.
+    fn len(self) -> usize {
+        match self {
+            Resolution::Int8 => 1,
+            Resolution::Int16 => 2,
+            Resolution::Int32 | Resolution::Float => 4,
+        }
+    }
+}
+
+/// A moteus register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    /// The control mode, e.g. `0` for stopped and `10` for position mode.
+    Mode,
+    /// The position of the output, in revolutions.
+    Position,
+    /// The velocity of the output, in revolutions per second.
+    Velocity,
+    /// The torque of the output, in Nm.
+    Torque,
+}
+
+impl Register {
+    /// The address of the register.
+    pub fn address(self) -> u16 {
+        match self {
+            Register::Mode => 0x000,
+            Register::Position => 0x001,
+            Register::Velocity => 0x002,
+            Register::Torque => 0x003,
+        }
+    }
+
+    /// The value of one count of an integer `resolution`.
+    fn scale(self, resolution: Resolution) -> f32 {
+        match (self, resolution) {
+            (_, Resolution::Float) | (Register::Mode, _) => 1.0,
+            (Register::Position, Resolution::Int8) => 0.01,
+            (Register::Position, Resolution::Int16) => 0.0001,
+            (Register::Position, Resolution::Int32) => 0.00001,
+            (Register::Velocity, Resolution::Int8) => 0.1,
+            (Register::Velocity, Resolution::Int16) => 0.00025,
+            (Register::Velocity, Resolution::Int32) => 0.00001,
+            (Register::Torque, Resolution::Int8) => 0.5,
+            (Register::Torque, Resolution::Int16) => 0.01,
+            (Register::Torque, Resolution::Int32) => 0.001,
+        }
+    }
+}
+
+/// Build a frame reading `register` from the moteus with id `destination`, which requests a reply.
+///
+/// Parse the reply with [`parse_register_reply`].
+pub fn read_register_frame(
+    destination: u8,
+    register: Register,
+    resolution: Resolution,
+) -> CanFdFrame {
+    let mut data = vec![READ | resolution.bits() << 2 | 1];
+    push_varuint(&mut data, register.address().into());
+    padded_frame(QUERY | u32::from(destination), data)
+}
+
+/// Build a frame writing `value` to `register` of the moteus with id `destination`, which does not request a reply.
+///
+/// `value` is rounded to the nearest count of an integer `resolution`, and saturates at its range.
+/// `NaN` is sent as the minimum integer value, which moteus treats as "not set".
+pub fn write_register_frame(
+    destination: u8,
+    register: Register,
+    value: f32,
+    resolution: Resolution,
+) -> CanFdFrame {
+    let mut data = vec![WRITE | resolution.bits() << 2 | 1];
+    push_varuint(&mut data, register.address().into());
+    let counts = value / register.scale(resolution);
+    // `as` saturates, but maps NaN to 0, so NaN is mapped to the minimum first
+    match resolution {
+        Resolution::Int8 => data.push(if value.is_nan() {
+            i8::MIN
+        } else {
+            counts.round() as i8
+        } as u8),
+        Resolution::Int16 => data.extend_from_slice(
+            &if value.is_nan() {
+                i16::MIN
+            } else {
+                counts.round() as i16
+            }
+            .to_le_bytes(),
+        ),
+        Resolution::Int32 => data.extend_from_slice(
+            &if value.is_nan() {
+                i32::MIN
+            } else {
+                counts.round() as i32
+            }
+            .to_le_bytes(),
+        ),
+        Resolution::Float => data.extend_from_slice(&value.to_le_bytes()),
+    }
+    padded_frame(u32::from(destination), data)
+}
+
+/// Find the value of `register` in the reply to a [`read_register_frame`].
+///
+/// Integer values are scaled to the units of the register, and the minimum integer value is returned as `NaN`.
+/// Returns an `Err` if the reply has no value for `register`, reports an error for it, or is malformed.
+pub fn parse_register_reply(frame: &CanFdFrame, register: Register) -> Result<f32, MoteusError> {
+    let data = &frame.data;
+    let mut pos = 0;
+    let malformed = |offset| MoteusError::Malformed { offset };
+    while let Some(&command) = data.get(pos) {
+        let offset = pos;
+        pos += 1;
+        match command {
+            NOP => continue,
+            _ if command & 0xF0 == REPLY => {}
+            WRITE_ERROR | READ_ERROR => {
+                let rejected = read_varuint(data, &mut pos).ok_or(malformed(offset))?;
+                let error = read_varuint(data, &mut pos).ok_or(malformed(offset))?;
+                if rejected == u32::from(register.address()) {
+                    return Err(MoteusError::Register {
+                        register: register.address(),
+                        error,
+                    });
+                }
+                continue;
+            }
+            // Including the stream subframes, 0x40 to 0x42, which do not carry registers
+            _ => return Err(malformed(offset)),
+        }
+        let resolution = Resolution::from_bits(command >> 2);
+        let count = match command & 0b11 {
+            0 => read_varuint(data, &mut pos).ok_or(malformed(offset))?,
+            count => u32::from(count),
+        };
+        let start = read_varuint(data, &mut pos).ok_or(malformed(offset))?;
+        let len = resolution.len();
+        let values = data
+            .get(pos..pos + count as usize * len)
+            .ok_or(malformed(offset))?;
+        pos += values.len();
+        let Some(index) = u32::from(register.address()).checked_sub(start) else {
+            continue;
+        };
+        let Some(value) = values.chunks_exact(len).nth(index as usize) else {
+            continue;
+        };
+        let scale = register.scale(resolution);
+        let value = match resolution {
+            Resolution::Int8 => scaled(i8::from_le_bytes([value[0]]).into(), i8::MIN.into(), scale),
+            Resolution::Int16 => scaled(
+                i16::from_le_bytes([value[0], value[1]]).into(),
+                i16::MIN.into(),
+                scale,
+            ),
+            Resolution::Int32 => scaled(
+                i32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+                i32::MIN,
+                scale,
+            ),
+            Resolution::Float => f32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+        };
+        return Ok(value);
+    }
+    Err(MoteusError::MissingRegister(register.address()))
+}
+
+impl<T, Buffer> FdCanUSB<T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Read `register` from the moteus with id `destination`, see [`read_register_frame`] and [`parse_register_reply`].
+    ///
+    /// Frames from other devices are queued for later reads, see [`FdCanUSB::query`].
+    pub fn read_register(
+        &mut self,
+        destination: u8,
+        register: Register,
+        resolution: Resolution,
+    ) -> Result<f32, MoteusError> {
+        let reply = self.query(read_register_frame(destination, register, resolution))?;
+        parse_register_reply(&reply, register)
+    }
+
+    /// Write `value` to `register` of the moteus with id `destination`, see [`write_register_frame`].
+    ///
+    /// The moteus does not reply, so this only waits for the FdCanUSB's `OK`.
+    pub fn write_register(
+        &mut self,
+        destination: u8,
+        register: Register,
+        value: f32,
+        resolution: Resolution,
+    ) -> Result<(), TransferError> {
+        self.write(write_register_frame(
+            destination,
+            register,
+            value,
+            resolution,
+        ))
+    }
+}
+
+fn scaled(value: i32, nan: i32, scale: f32) -> f32 {
+    if value == nan {
+        f32::NAN
+    } else {
+        value as f32 * scale
+    }
+}
+
+/// Pads `data` with no-op subframes to a valid DLC.
+fn padded_frame(arbitration_id: u32, mut data: Vec<u8>) -> CanFdFrame {
+    if let Some(len) = round_up_dlc(data.len()) {
+        data.resize(len, NOP);
+    }
+    CanFdFrame::new(crate::CanId::Extended(arbitration_id), &data)
+        .expect("register subframes fit in a frame")
+}
+
+/// Writes `value` as a little-endian base 128 integer, 7 bits per byte.
+fn push_varuint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varuint(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u32::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_frames() {
+        let frame = read_register_frame(1, Register::Position, Resolution::Float);
+        assert_eq!(frame.arbitration_id.as_raw(), 0x8001);
+        assert_eq!(frame.data, [0x1D, 0x01]);
+
+        let frame = write_register_frame(2, Register::Velocity, 0.5, Resolution::Int16);
+        assert_eq!(frame.arbitration_id.as_raw(), 0x0002);
+        assert_eq!(frame.data, [0x05, 0x02, 0xD0, 0x07]);
+
+        let frame = write_register_frame(2, Register::Torque, f32::NAN, Resolution::Int8);
+        assert_eq!(frame.data, [0x01, 0x03, 0x80]);
+    }
+
+    #[test]
+    fn test_parse_register_reply() {
+        // Mode and position as int16, then torque as a float
+        let mut data = vec![0x26, 0x00, 0x0A, 0x00, 0x10, 0x27, 0x2D, 0x03];
+        data.extend_from_slice(&1.5f32.to_le_bytes());
+        data.extend_from_slice(&[0x50; 4]);
+        let reply = CanFdFrame::new(0x0100, &data).unwrap();
+        assert_eq!(parse_register_reply(&reply, Register::Mode).unwrap(), 10.0);
+        let position = parse_register_reply(&reply, Register::Position).unwrap();
+        assert!((position - 1.0).abs() < 1e-6);
+        assert_eq!(parse_register_reply(&reply, Register::Torque).unwrap(), 1.5);
+        assert!(matches!(
+            parse_register_reply(&reply, Register::Velocity),
+            Err(MoteusError::MissingRegister(0x002))
+        ));
+
+        let reply = CanFdFrame::new(0x0100, &[0x21, 0x02, 0x80]).unwrap();
+        assert!(parse_register_reply(&reply, Register::Velocity)
+            .unwrap()
+            .is_nan());
+
+        let reply = CanFdFrame::new(0x0100, &[0x31, 0x02, 0x05]).unwrap();
+        assert!(matches!(
+            parse_register_reply(&reply, Register::Velocity),
+            Err(MoteusError::Register {
+                register: 0x002,
+                error: 5
+            })
+        ));
+
+        let reply = CanFdFrame::new(0x0100, &[0x40, 0x01, 0x00]).unwrap();
+        assert!(matches!(
+            parse_register_reply(&reply, Register::Mode),
+            Err(MoteusError::Malformed { offset: 0 })
+        ));
+
+        let reply = CanFdFrame::new(0x0100, &[0x22, 0x00, 0x01]).unwrap();
+        assert!(matches!(
+            parse_register_reply(&reply, Register::Mode),
+            Err(MoteusError::Malformed { offset: 0 })
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::{Duration, Instant};
+
+use crate::bus::FdCanUSB;
+use crate::error::{SegmentError, TransferError, WriteError};
+use crate::frames::CanFdFrame;
+
+/// The time each device is polled for, see [`FdCanUSBBus::set_poll_interval`].
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// The time [`FdCanUSBBus::read`] waits for a frame.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Several [`FdCanUSB`]s, each on its own CAN segment, used as one interface.
+///
+/// Each device is added with a segment id `K`, e.g. a name or number, which frames are written to and read from.
+///
+/// Reads poll the devices in turn, see [`FdCanUSBBus::read_timeout`].
+#[derive(Debug)]
+pub struct FdCanUSBBus<K, T, Buffer = Vec<u8>>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    devices: Vec<(K, FdCanUSB<T, Buffer>)>,
+    /// The index of the device polled first by the next read
+    next: usize,
+    poll_interval: Duration,
+}
+
+impl<K, T, Buffer> Default for FdCanUSBBus<K, T, Buffer>
+where
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn default() -> Self {
+        FdCanUSBBus {
+            devices: Vec::new(),
+            next: 0,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+impl<K, T, Buffer> FdCanUSBBus<K, T, Buffer>
+where
+    K: PartialEq + Clone + std::fmt::Debug,
+    T: std::io::Write + std::io::Read,
+    Buffer: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Create a bus with no devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a device for `segment`, replacing and returning any device already added for it.
+    pub fn insert(
+        &mut self,
+        segment: K,
+        fdcanusb: FdCanUSB<T, Buffer>,
+    ) -> Option<FdCanUSB<T, Buffer>> {
+        match self.get_mut(&segment) {
+            Some(device) => Some(std::mem::replace(device, fdcanusb)),
+            None => {
+                self.devices.push((segment, fdcanusb));
+                None
+            }
+        }
+    }
+
+    /// Get the device for `segment`, e.g. to configure it or transfer frames with it directly.
+    pub fn get(&self, segment: &K) -> Option<&FdCanUSB<T, Buffer>> {
+        self.devices
+            .iter()
+            .find(|(k, _)| k == segment)
+            .map(|(_, device)| device)
+    }
+
+    /// Get the device for `segment` mutably, see [`FdCanUSBBus::get`].
+    pub fn get_mut(&mut self, segment: &K) -> Option<&mut FdCanUSB<T, Buffer>> {
+        self.devices
+            .iter_mut()
+            .find(|(k, _)| k == segment)
+            .map(|(_, device)| device)
+    }
+
+    /// The segments of the devices, in the order they were added.
+    pub fn segments(&self) -> impl Iterator<Item = &K> {
+        self.devices.iter().map(|(k, _)| k)
+    }
+
+    /// Write a frame to the device for `segment`, see [`FdCanUSB::write`].
+    ///
+    /// Returns a `NotFound` IO error if no device was added for `segment`.
+    pub fn write(&mut self, segment: &K, frame: CanFdFrame) -> Result<(), TransferError> {
+        let device = self.get_mut(segment).ok_or_else(|| {
+            WriteError::from(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no FdCanUSB for segment {segment:?}"),
+            ))
+        })?;
+        device.write(frame)
+    }
+
+    /// Set the longest time one device is waited for, before the next device is polled, which defaults to 1ms.
+    ///
+    /// A shorter interval returns frames sooner, at the cost of more reads.
+    pub fn set_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Read a frame from any device, with the segment it was read from.
+    ///
+    /// See [`FdCanUSBBus::read_timeout`].
+    pub fn read(&mut self) -> Result<Option<(K, CanFdFrame)>, SegmentError<K>> {
+        self.read_timeout(DEFAULT_TIMEOUT)
+    }
+
+    /// Read a frame from any device, with the segment it was read from, waiting up to `timeout` for one to arrive.
+    ///
+    /// The devices are polled in turn, each for up to the [poll interval](FdCanUSBBus::set_poll_interval),
+    /// so a frame already buffered by a device is returned without waiting.
+    /// Each read starts with the device after the one which returned the last frame,
+    /// so a busy segment can not starve the others: with frames waiting on every segment, they are returned round-robin.
+    /// The poll interval is only kept if the transport's read timeout can be set, e.g. a port opened with [`FdCanUSB::open`],
+    /// otherwise each poll waits for the transport's own read timeout.
+    ///
+    /// Returns `Ok(None)` if no frame arrives before the timeout, and any other error with the segment of the device.
+    pub fn read_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<(K, CanFdFrame)>, SegmentError<K>> {
+        if self.devices.is_empty() {
+            return Ok(None);
+        }
+        let len = self.devices.len();
+        let deadline = Instant::now() + timeout;
+        loop {
+            for offset in 0..len {
+                let index = (self.next + offset) % len;
+                let poll = deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(self.poll_interval);
+                let (segment, device) = &mut self.devices[index];
+                let result = match device.read_timeout(poll) {
+                    Err(e) if e.is_timeout() => continue,
+                    result => result,
+                };
+                self.next = (index + 1) % len;
+                return match result {
+                    Ok(frame) => Ok(Some((segment.clone(), frame))),
+                    Err(source) => Err(SegmentError {
+                        segment: segment.clone(),
+                        source,
+                    }),
+                };
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::tests::MockTransport;
+    use crate::error::ReadError;
+
+    #[test]
+    fn test_round_robin_read() {
+        let mut bus = FdCanUSBBus::new();
+        bus.insert(
+            "a",
+            FdCanUSB::new(MockTransport::new(b"rcv 0100 01\r\nrcv 0100 02\r\n")),
+        );
+        bus.insert("b", FdCanUSB::new(MockTransport::new(b"rcv 0200 03\r\n")));
+        let mut read = || {
+            let (segment, frame) = bus.read().unwrap().unwrap();
+            (segment, frame.data[0])
+        };
+        assert_eq!(read(), ("a", 1));
+        assert_eq!(read(), ("b", 3));
+        assert_eq!(read(), ("a", 2));
+        assert!(bus
+            .read_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_segment_write() {
+        let mut bus = FdCanUSBBus::new();
+        bus.insert(1, FdCanUSB::new(MockTransport::new(b"OK\r\n")));
+        bus.insert(2, FdCanUSB::new(MockTransport::default()));
+        bus.write(&1, CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        assert_eq!(bus.get(&1).unwrap().get_ref().tx, b"can send 8001 01 E\n");
+        assert!(bus.get(&2).unwrap().get_ref().tx.is_empty());
+        assert!(bus
+            .write(&3, CanFdFrame::new(0x8001, &[1]).unwrap())
+            .is_err());
+        assert_eq!(bus.segments().collect::<Vec<_>>(), [&1, &2]);
+    }
+
+    #[test]
+    fn test_segment_error() {
+        let mut bus = FdCanUSBBus::new();
+        bus.insert("a", FdCanUSB::new(MockTransport::new(b"OK\r\n")));
+        let error = bus.read().unwrap_err();
+        assert_eq!(error.segment, "a");
+        assert!(matches!(error.source, ReadError::LostSync { .. }));
+    }
+}
@@ -0,0 +1,281 @@
+use std::io::{BufRead, Read, Write};
+use std::time::{Duration, Instant};
+
+/// A transport wrapper that records every byte written and read to `out`, to be replayed by a [`ReplayTransport`].
+///
+/// Each write and read is recorded as a line:
+/// `<microseconds since the first record> <tx|rx> <hex bytes>`, e.g. `1500 rx 4f4b0d0a` for an `OK\r\n` read 1.5ms in.
+/// Failed reads and writes, such as timeouts, are not recorded.
+/// If writing to `out` fails, reads and writes of the transport still succeed, recording stops,
+/// and the error is returned by the next [`flush`](Write::flush), see [`RecordTransport::take_error`].
+/// A recording is plain text, so it can also be read or edited by hand.
+/// Enable with the `record` feature.
+#[derive(Debug)]
+pub struct RecordTransport<T, W: Write> {
+    inner: T,
+    out: W,
+    /// The time of the first record, which the times of the following records are relative to.
+    start: Option<Instant>,
+    /// The error which stopped the recording, if writing to `out` failed.
+    error: Option<std::io::Error>,
+}
+
+impl<T, W: Write> RecordTransport<T, W> {
+    /// Wrap `inner`, recording its IO to `out`.
+    pub fn new(inner: T, out: W) -> Self {
+        RecordTransport {
+            inner,
+            out,
+            start: None,
+            error: None,
+        }
+    }
+
+    /// Get a reference to the underlying transport.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying transport.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consume the `RecordTransport`, returning the underlying transport and the recording.
+    pub fn into_inner(self) -> (T, W) {
+        (self.inner, self.out)
+    }
+
+    /// Take the error which stopped the recording, if writing to `out` failed, which also restarts the recording.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.error.take()
+    }
+
+    /// Record `bytes`, unless the recording has stopped.
+    /// An error is kept rather than returned, so the bytes already moved by the transport are not lost.
+    fn record(&mut self, direction: &str, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(*self.start.get_or_insert(now));
+        if let Err(e) = writeln!(
+            self.out,
+            "{} {direction} {}",
+            elapsed.as_micros(),
+            hex::encode(bytes)
+        ) {
+            debug!("recording stopped: {}", e);
+            self.error = Some(e);
+        }
+    }
+}
+
+impl<T: Read, W: Write> Read for RecordTransport<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.record("rx", &buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write, W: Write> Write for RecordTransport<T, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.record("tx", &buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+}
+
+/// A transport which replays the bytes read in a recording made by a [`RecordTransport`], to test code offline.
+///
+/// Reads return the recorded reads in order, each no earlier than its recorded time after the first read or write,
+/// so a session's timing, including slow responses and timeouts, is reproduced.
+/// Once every recorded read is returned, reads fail with a [`TimedOut`](std::io::ErrorKind::TimedOut) error,
+/// as a serial port with no data would.
+///
+/// Writes are accepted and discarded, they can be checked with [`ReplayTransport::written`].
+/// Enable with the `record` feature.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    /// The recorded reads, with their times, in reverse order so the next is at the end.
+    reads: Vec<(Duration, Vec<u8>)>,
+    /// The bytes of the current read not yet returned.
+    current: std::io::Cursor<Vec<u8>>,
+    written: Vec<u8>,
+    /// The time of the first read or write, which the recorded times are relative to.
+    start: Option<Instant>,
+    /// If `false`, reads return immediately rather than waiting for their recorded time.
+    timing: bool,
+}
+
+impl ReplayTransport {
+    /// Parse a recording made by a [`RecordTransport`].
+    ///
+    /// Returns an [`InvalidData`](std::io::ErrorKind::InvalidData) error if a line is not a valid record.
+    pub fn new(recording: impl BufRead) -> std::io::Result<Self> {
+        let mut reads = Vec::new();
+        for line in recording.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid record {line:?}"),
+                )
+            };
+            let mut fields = line.split(' ');
+            let (Some(micros), Some(direction), Some(bytes), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let time = Duration::from_micros(micros.parse().map_err(|_| invalid())?);
+            let bytes = hex::decode(bytes).map_err(|_| invalid())?;
+            match direction {
+                "rx" => reads.push((time, bytes)),
+                "tx" => {}
+                _ => return Err(invalid()),
+            }
+        }
+        reads.reverse();
+        Ok(ReplayTransport {
+            reads,
+            current: std::io::Cursor::default(),
+            written: Vec::new(),
+            start: None,
+            timing: true,
+        })
+    }
+
+    /// Return recorded reads immediately, rather than at their recorded time, e.g. for fast tests.
+    pub fn without_timing(mut self) -> Self {
+        self.timing = false;
+        self
+    }
+
+    /// The bytes written to the transport, to compare with the recording.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    fn start(&mut self) -> Instant {
+        *self.start.get_or_insert_with(Instant::now)
+    }
+}
+
+impl Read for ReplayTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = self.start();
+        if self.current.position() as usize == self.current.get_ref().len() {
+            let Some((time, bytes)) = self.reads.pop() else {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            };
+            if self.timing {
+                std::thread::sleep((start + time).saturating_duration_since(Instant::now()));
+            }
+            self.current = std::io::Cursor::new(bytes);
+        }
+        self.current.read(buf)
+    }
+}
+
+impl Write for ReplayTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.start();
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CanFdFrame, FdCanUSB};
+
+    /// A device that replies `OK` and a `rcv` frame to each write.
+    #[derive(Debug, Default)]
+    struct Echo(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Echo {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(std::io::ErrorKind::TimedOut.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Echo {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 = std::io::Cursor::new(b"OK\r\nrcv 0100 01\r\n".to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let frame = CanFdFrame::new(0x8001, &[1]).unwrap();
+        let mut fdcanusb = FdCanUSB::new(RecordTransport::new(Echo::default(), Vec::new()));
+        let recorded = fdcanusb.transfer_single(frame.clone(), true).unwrap();
+        let (_, recording) = fdcanusb.into_inner().into_inner();
+        let recording = String::from_utf8(recording).unwrap();
+        assert!(recording.starts_with("0 tx 63616e2073656e64"));
+        assert!(recording.contains(" rx 4f4b0d0a72637620"));
+
+        let replay = ReplayTransport::new(recording.as_bytes()).unwrap();
+        let mut fdcanusb = FdCanUSB::new(replay.without_timing());
+        let replayed = fdcanusb.transfer_single(frame, true).unwrap();
+        assert_eq!(replayed, recorded);
+        assert_eq!(fdcanusb.get_ref().written(), b"can send 8001 01 E\n");
+        assert!(fdcanusb.read().unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn test_record_error() {
+        let mut out = [0; 8];
+        let mut transport = RecordTransport::new(Echo::default(), &mut out[..]);
+        // The record does not fit in `out`, but the bytes still reach the transport
+        assert_eq!(transport.write(b"can send 8001 01\n").unwrap(), 17);
+        let mut buf = [0; 4];
+        assert_eq!(transport.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"OK\r\n");
+        assert!(transport.flush().is_err());
+        assert!(transport.take_error().is_none());
+    }
+
+    #[test]
+    fn test_replay_timing() {
+        let replay = ReplayTransport::new(&b"20000 rx 4f4b0d0a\n"[..]).unwrap();
+        let mut fdcanusb = FdCanUSB::new(replay);
+        let start = Instant::now();
+        fdcanusb
+            .write(CanFdFrame::new(0x8001, &[1]).unwrap())
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(ReplayTransport::new(&b"0 rx zz\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::ReadError;
+
+/// Counters describing the traffic and errors seen by a [`FdCanUSB`](crate::FdCanUSB).
+///
+/// Access them with [`FdCanUSB::stats`](crate::FdCanUSB::stats),
+/// and reset them with [`FdCanUSB::reset_stats`](crate::FdCanUSB::reset_stats).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of frames written to the FdCanUSB.
+    pub frames_sent: u64,
+    /// The number of `rcv` frames read from the FdCanUSB.
+    pub frames_received: u64,
+    /// The number of times an unexpected packet was read.
+    pub lost_sync: u64,
+    /// The number of error replies from the FdCanUSB.
+    pub device_errors: u64,
+    /// The number of times a read timed out.
+    pub timeouts: u64,
+    /// The number of packets that could not be parsed, or were too long.
+    pub parse_errors: u64,
+}
+
+impl Stats {
+    /// Increment the counter matching `error`.
+    // `ReadError::Uft8` is deprecated, but still counted until it is removed
+    #[allow(deprecated)]
+    pub(crate) fn record_error(&mut self, error: &ReadError) {
+        match error {
+            ReadError::Timeout(_) => self.timeouts += 1,
+            ReadError::Io(_) => {}
+            ReadError::Uft8(_) | ReadError::Parse(_) | ReadError::LineTooLong { .. } => {
+                self.parse_errors += 1
+            }
+            ReadError::LostSync { .. } => self.lost_sync += 1,
+            ReadError::DeviceError { .. } => self.device_errors += 1,
+        }
+    }
+}